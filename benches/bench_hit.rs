@@ -4,13 +4,13 @@ extern crate test;
 extern crate raytracer;
 use test::Bencher;
 use raytracer::*;
-use std::rc::Rc;
+use std::sync::Arc;
 
 #[bench]
 fn bench_hit_sphere(b: &mut Bencher) {
     let mat = Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(1.0, 0.0, 0.0))));
     let mut rng = Rng::new();
-    let s = Sphere::new(Vec3::new(3.0, 0.2, 4.0), 0.2, Rc::new(mat));
+    let s = Sphere::new(Vec3::new(3.0, 0.2, 4.0), 0.2, Arc::new(mat));
     let r = Ray::new(Vec3::new(13.,2.,3.),
                      Vec3::new(-10., 0.0, -2.0));
     b.iter(|| s.hit(&mut rng, &r, 0.0, 1.0));
@@ -20,10 +20,118 @@ fn bench_hit_sphere(b: &mut Bencher) {
 fn bench_hit_aabb(b: &mut Bencher) {
     let mat = Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(1.0, 0.0, 0.0))));
     let mut rng = Rng::new();
-    let s = Sphere::new(Vec3::new(3.0, 0.2, 4.0), 0.2, Rc::new(mat));
+    let s = Sphere::new(Vec3::new(3.0, 0.2, 4.0), 0.2, Arc::new(mat));
     let r = Ray::new(Vec3::new(13.,2.,3.),
                      Vec3::new(-10., 0.0, -2.0));
     let bbox = s.bounding_box(0.0, 1.0).unwrap();
     b.iter(|| bbox.hit(&mut rng, &r, 0.0, 1.0));
 
 }
+
+/// Rays from around the camera position of the scene, towards random points
+/// in `target` (with the given size).
+fn bench_rays(rng: &mut Rng, lookfrom: Vec3<f64>, target: Vec3<f64>, size: f64) -> Vec<Ray<f64>> {
+    (0..1000).map(|_| {
+        let p = target + size * Vec3::new(rng.rand64() - 0.5, rng.rand64() - 0.5, rng.rand64() - 0.5);
+        Ray::new(lookfrom, p - lookfrom)
+    }).collect()
+}
+
+/// The small spheres from the "Ray Tracing in One Weekend" cover.
+fn random_scene(rng: &mut Rng) -> BVH {
+    let mut list: Vec<Box<Hitable>> = Vec::new();
+    let mat: Arc<Material> = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.5, 0.5, 0.5)))));
+    list.push(Box::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0), 1000.0, mat.clone())));
+    for a in -10..10 {
+        for b in -10..10 {
+            let center = Vec3::new(a as f64 + 0.9 * rng.rand64(), 0.2, b as f64 + 0.9 * rng.rand64());
+            list.push(Box::new(Sphere::new(center, 0.2, mat.clone())));
+        }
+    }
+    for x in &[-4.0, 0.0, 4.0] {
+        list.push(Box::new(Sphere::new(Vec3::new(*x, 1.0, 0.0), 1.0, mat.clone())));
+    }
+    BVH::new(list, 0.0, 1.0)
+}
+
+/// The floor of boxes and the cube of spheres from the "Ray Tracing: The
+/// Next Week" final scene.
+fn final_scene(rng: &mut Rng) -> HitableList {
+    let mat: Arc<Material> = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.5, 0.5, 0.5)))));
+    let mut list = HitableList::new();
+    let mut boxes: Vec<Box<Hitable>> = Vec::new();
+    for i in 0..20 {
+        for j in 0..20 {
+            let w = 100.;
+            let x0 = -1000. + i as f64 * w;
+            let z0 = -1000. + j as f64 * w;
+            let y1 = 100. * (rng.rand64() + 0.01);
+            boxes.push(Box::new(HBox::new(Vec3::new(x0, 0., z0), Vec3::new(x0 + w, y1, z0 + w), mat.clone())));
+        }
+    }
+    list.add_hitable(BVH::new(boxes, 0., 1.));
+    let mut spheres: Vec<Box<Hitable>> = Vec::new();
+    for _ in 0..1000 {
+        let center = Vec3::new(165. * rng.rand64(), 165. * rng.rand64(), 165. * rng.rand64());
+        spheres.push(Box::new(Sphere::new(center, 10., mat.clone())));
+    }
+    list.add_hitable(Translate::new(Box::new(RotateY::new(Box::new(BVH::new(spheres, 0., 1.)), 15.)),
+                                    Vec3::new(-100., 270., 395.)));
+    list
+}
+
+#[bench]
+fn bench_hit_random_scene(b: &mut Bencher) {
+    let mut rng = Rng::from_seed(0);
+    let world = random_scene(&mut rng);
+    let rays = bench_rays(&mut rng, Vec3::new(13., 2., 3.), Vec3::new(0., 0.5, 0.), 20.);
+    b.iter(|| {
+        rays.iter().filter(|r| world.hit(&mut rng, r, 0.001, f64::MAX).is_some()).count()
+    });
+}
+
+#[bench]
+fn bench_hit_final_scene(b: &mut Bencher) {
+    let mut rng = Rng::from_seed(0);
+    let world = final_scene(&mut rng);
+    let rays = bench_rays(&mut rng, Vec3::new(478., 278., -600.), Vec3::new(0., 150., 200.), 1000.);
+    b.iter(|| {
+        rays.iter().filter(|r| world.hit(&mut rng, r, 0.001, f64::MAX).is_some()).count()
+    });
+}
+
+/// A bumpy grid of `2*n*n` triangles, one unit across.
+fn grid_mesh(n: usize) -> TriangleMesh {
+    let mat: Arc<Material> = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.5, 0.5, 0.5)))));
+    let mut positions = Vec::with_capacity((n + 1) * (n + 1));
+    for i in 0..n+1 {
+        for j in 0..n+1 {
+            let (x, z) = (i as f64 / n as f64, j as f64 / n as f64);
+            positions.push(Vec3::new(x, 0.05 * (20. * x).sin() * (20. * z).cos(), z));
+        }
+    }
+    let mut triangles = Vec::with_capacity(2 * n * n);
+    for i in 0..n {
+        for j in 0..n {
+            let a = i * (n + 1) + j;
+            triangles.push([a, a + 1, a + n + 1]);
+            triangles.push([a + 1, a + n + 2, a + n + 1]);
+        }
+    }
+    TriangleMesh::new(positions, Vec::new(), Vec::new(), triangles, mat)
+}
+
+#[bench]
+fn bench_build_mesh(b: &mut Bencher) {
+    b.iter(|| grid_mesh(100).num_triangles());
+}
+
+#[bench]
+fn bench_hit_mesh(b: &mut Bencher) {
+    let mut rng = Rng::from_seed(0);
+    let mesh = grid_mesh(300);
+    let rays = bench_rays(&mut rng, Vec3::new(0.5, 2., -1.), Vec3::new(0.5, 0., 0.5), 1.);
+    b.iter(|| {
+        rays.iter().filter(|r| mesh.hit(&mut rng, r, 0.001, f64::MAX).is_some()).count()
+    });
+}
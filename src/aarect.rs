@@ -12,7 +12,7 @@ pub struct XYRect {
     y0: f64,
     y1: f64,
     k: f64,
-    material: Rc<Material>
+    material: Arc<Material>
 }
 
 
@@ -38,6 +38,25 @@ impl Hitable for XYRect {
         Some(AABB::new(Vec3::new(self.x0, self.y0, self.k-0.0001),
                        Vec3::new(self.x1, self.y1, self.k+0.0001)))
     }
+
+    fn pdf_value(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        if let Some(rec) = self.hit(rng, &Ray::new(o.clone(), v.clone()), 0.001, f64::MAX) {
+            let area = (self.x1-self.x0)*(self.y1-self.y0);
+            let distance_squared = rec.t*rec.t*v.squared_length();
+            let cosine = dot(v, &rec.normal).abs() / v.length();
+            return distance_squared / (cosine * area);
+        } else {
+            return 0.;
+        }
+    }
+    fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> {
+        let random_point = Vec3::new(self.x0 + rng.rand64()*(self.x1-self.x0), self.y0 + rng.rand64()*(self.y1-self.y0), self.k);
+        return random_point - *o;
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
 }
 
 #[derive(Debug, new)]
@@ -47,7 +66,7 @@ pub struct XZRect {
     z0: f64,
     z1: f64,
     k: f64,
-    material: Rc<Material>
+    material: Arc<Material>
 }
 
 
@@ -87,6 +106,9 @@ impl Hitable for XZRect {
         let random_point = Vec3::new(self.x0 + rng.rand64()*(self.x1-self.x0), self.k, self.z0 + rng.rand64()*(self.z1-self.z0));
         return random_point - *o;
     }
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
 }
 
 #[derive(Debug, new)]
@@ -96,7 +118,7 @@ pub struct YZRect {
     z0: f64,
     z1: f64,
     k: f64,
-    material: Rc<Material>
+    material: Arc<Material>
 }
 
 
@@ -122,4 +144,38 @@ impl Hitable for YZRect {
         Some(AABB::new(Vec3::new(self.k-0.0001, self.y0, self.z0),
                        Vec3::new(self.k+0.0001, self.y1, self.z1)))
     }
+
+    fn pdf_value(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        if let Some(rec) = self.hit(rng, &Ray::new(o.clone(), v.clone()), 0.001, f64::MAX) {
+            let area = (self.y1-self.y0)*(self.z1-self.z0);
+            let distance_squared = rec.t*rec.t*v.squared_length();
+            let cosine = dot(v, &rec.normal).abs() / v.length();
+            return distance_squared / (cosine * area);
+        } else {
+            return 0.;
+        }
+    }
+    fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> {
+        let random_point = Vec3::new(self.k, self.y0 + rng.rand64()*(self.y1-self.y0), self.z0 + rng.rand64()*(self.z1-self.z0));
+        return random_point - *o;
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use texture::*;
+
+    #[test]
+    fn test_rect_sampling() {
+        let m: Arc<Material> = Arc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(1., 1., 1.)))));
+        let o = Vec3::new(0.5, 0.2, 0.3);
+        check_sampling(&XYRect::new(-1., 2., -1., 1.5, 1.5, m.clone()), &o);
+        check_sampling(&XZRect::new(-1., 2., -1., 1.5, -0.8, m.clone()), &o);
+        check_sampling(&YZRect::new(-1., 2., -1., 1.5, 1.2, m.clone()), &o);
+    }
 }
@@ -0,0 +1,257 @@
+use vec3::*;
+use util::*;
+use image;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+/// What a ray sees when it doesn't hit anything.
+pub trait Background: fmt::Debug + Send + Sync {
+    /// Radiance arriving from the opposite of `direction` (the direction of
+    /// the ray that missed).
+    fn value(&self, direction: &Vec3<f64>) -> Vec3<f64>;
+
+    /// Whether the background should be importance sampled like a light.
+    fn is_sampled(&self) -> bool { false }
+
+    fn pdf_value(&self, direction: &Vec3<f64>) -> f64 { 0. }
+    fn random(&self, rng: &mut Rng) -> Vec3<f64> { Vec3::new(1., 0., 0.) }
+}
+
+/// The same color in every direction.
+#[derive(Debug, new)]
+pub struct ConstantBackground {
+    color: Vec3<f64>,
+}
+
+impl Background for ConstantBackground {
+    fn value(&self, direction: &Vec3<f64>) -> Vec3<f64> {
+        self.color
+    }
+}
+
+/// A simple sky, blending from `bottom` straight down to `top` straight up.
+#[derive(Debug, new)]
+pub struct GradientBackground {
+    bottom: Vec3<f64>,
+    top: Vec3<f64>,
+}
+
+impl GradientBackground {
+    /// White to light blue.
+    pub fn sky() -> GradientBackground {
+        GradientBackground::new(Vec3::new(1.0, 1.0, 1.0), Vec3::new(0.5, 0.7, 1.0))
+    }
+}
+
+impl Background for GradientBackground {
+    fn value(&self, direction: &Vec3<f64>) -> Vec3<f64> {
+        let unit_direction = direction.unit_vector();
+        let t = 0.5 * (unit_direction.y + 1.0);
+        (1.0 - t) * self.bottom + t * self.top
+    }
+}
+
+/// An equirectangular (latitude/longitude) image surrounding the scene.
+///
+/// The top row of the image is straight up (+y), and the center of the
+/// image faces -z.  Directions are sampled in proportion to the brightness
+/// of the pixels, so a small bright sun doesn't make the image noisy.
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    /// Linear RGB, top row first.
+    pixels: Vec<Vec3<f64>>,
+    /// Rotation about the y axis, in radians.
+    rotation: f64,
+    /// Cumulative distribution for picking a row, `height + 1` entries.
+    row_cdf: Vec<f64>,
+    /// Cumulative distribution for picking a column within each row,
+    /// `width + 1` entries per row.
+    column_cdf: Vec<Vec<f64>>,
+}
+
+impl fmt::Debug for EnvironmentMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EnvironmentMap{{ width: {}, height: {}}}", self.width, self.height)
+    }
+}
+
+/// Turn a list of weights into a cumulative distribution from 0 to 1.
+/// Returns the distribution and the total weight.
+fn build_cdf(weights: &[f64]) -> (Vec<f64>, f64) {
+    let mut cdf = Vec::with_capacity(weights.len() + 1);
+    let mut total = 0.;
+    cdf.push(0.);
+    for w in weights {
+        total += *w;
+        cdf.push(total);
+    }
+    if total > 0. {
+        for c in cdf.iter_mut() {
+            *c /= total;
+        }
+    } else {
+        // Nothing to prefer, so sample uniformly.
+        let n = weights.len() as f64;
+        for (i, c) in cdf.iter_mut().enumerate() {
+            *c = i as f64 / n;
+        }
+    }
+    (cdf, total)
+}
+
+/// Pick an entry from a cumulative distribution.  Returns the index, and
+/// where `u` falls within the entry (0 to 1).
+fn sample_cdf(cdf: &[f64], u: f64) -> (usize, f64) {
+    // The last entry whose start is <= u.
+    let i = match cdf.binary_search_by(|c| c.partial_cmp(&u).unwrap()) {
+        Ok(i) => i,
+        Err(i) => i - 1,
+    };
+    let i = i.min(cdf.len() - 2);
+    // Skip entries with zero probability.
+    let mut i = i;
+    while cdf[i + 1] <= cdf[i] && i + 2 < cdf.len() {
+        i += 1;
+    }
+    let width = cdf[i + 1] - cdf[i];
+    let t = if width > 0. { ((u - cdf[i]) / width).max(0.).min(1.) } else { 0.5 };
+    (i, t)
+}
+
+impl EnvironmentMap {
+    /// `pixels` is linear RGB, top row first.  `rotation` turns the map
+    /// about the y axis (in degrees).
+    pub fn new(width: usize, height: usize, pixels: Vec<Vec3<f64>>, rotation: f64) -> EnvironmentMap {
+        assert_eq!(pixels.len(), width * height);
+        let mut row_weights = Vec::with_capacity(height);
+        let mut column_cdf = Vec::with_capacity(height);
+        for y in 0..height {
+            // Rows near the poles cover less of the sphere.
+            let sin_theta = (PI * (y as f64 + 0.5) / height as f64).sin();
+            let weights: Vec<f64> = pixels[y*width..(y+1)*width].iter()
+                .map(|p| luminance(p).max(0.) * sin_theta)
+                .collect();
+            let (cdf, total) = build_cdf(&weights);
+            column_cdf.push(cdf);
+            row_weights.push(total);
+        }
+        let (row_cdf, _) = build_cdf(&row_weights);
+        EnvironmentMap {
+            width: width,
+            height: height,
+            pixels: pixels,
+            rotation: rotation.to_radians(),
+            row_cdf: row_cdf,
+            column_cdf: column_cdf,
+        }
+    }
+
+    /// Load a Radiance .hdr image.  `rotation` turns the map about the y
+    /// axis (in degrees).
+    pub fn load<P: AsRef<Path>>(path: P, rotation: f64) -> image::ImageResult<EnvironmentMap> {
+        let f = BufReader::new(File::open(path)?);
+        let decoder = image::hdr::HDRDecoder::new(f)?;
+        let meta = decoder.metadata();
+        let pixels = decoder.read_image_hdr()?.iter()
+            .map(|p| Vec3::new(p.data[0] as f64, p.data[1] as f64, p.data[2] as f64))
+            .collect();
+        Ok(EnvironmentMap::new(meta.width as usize, meta.height as usize, pixels, rotation))
+    }
+
+    /// Image coordinates (0 to 1) for a direction.
+    fn direction_to_uv(&self, direction: &Vec3<f64>) -> (f64, f64) {
+        let d = direction.unit_vector();
+        let phi = d.x.atan2(-d.z) - self.rotation;
+        let theta = d.y.max(-1.).min(1.).acos();
+        let u = 0.5 + phi / (2. * PI);
+        (u - u.floor(), theta / PI)
+    }
+
+    fn uv_to_direction(&self, u: f64, v: f64) -> Vec3<f64> {
+        let phi = 2. * PI * (u - 0.5) + self.rotation;
+        let theta = PI * v;
+        Vec3::new(theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos())
+    }
+
+    fn pixel_index(&self, u: f64, v: f64) -> (usize, usize) {
+        let x = ((u * self.width as f64) as usize).min(self.width - 1);
+        let y = ((v * self.height as f64) as usize).min(self.height - 1);
+        (x, y)
+    }
+}
+
+impl Background for EnvironmentMap {
+    fn value(&self, direction: &Vec3<f64>) -> Vec3<f64> {
+        let (u, v) = self.direction_to_uv(direction);
+        let (x, y) = self.pixel_index(u, v);
+        self.pixels[y * self.width + x]
+    }
+
+    fn is_sampled(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, direction: &Vec3<f64>) -> f64 {
+        let (u, v) = self.direction_to_uv(direction);
+        let (x, y) = self.pixel_index(u, v);
+        let sin_theta = (PI * v).sin();
+        if sin_theta <= 0. {
+            return 0.;
+        }
+        let columns = &self.column_cdf[y];
+        let p = (self.row_cdf[y + 1] - self.row_cdf[y]) * (columns[x + 1] - columns[x]);
+        // The pixel covers 1/(width*height) of the image, and the image
+        // covers 2*pi*pi*sin(theta) steradians per unit area.
+        p * (self.width * self.height) as f64 / (2. * PI * PI * sin_theta)
+    }
+
+    fn random(&self, rng: &mut Rng) -> Vec3<f64> {
+        let (y, tv) = sample_cdf(&self.row_cdf, rng.rand64());
+        let (x, tu) = sample_cdf(&self.column_cdf[y], rng.rand64());
+        let u = (x as f64 + tu) / self.width as f64;
+        let v = (y as f64 + tv) / self.height as f64;
+        self.uv_to_direction(u, v)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pdf::random_on_unit_sphere;
+
+    #[test]
+    fn test_environment_map() {
+        // A dim map with one bright pixel.
+        let (width, height) = (16, 8);
+        let mut pixels = vec![Vec3::new(0.1, 0.1, 0.1); width * height];
+        pixels[2 * width + 5] = Vec3::new(100., 100., 100.);
+        let map = EnvironmentMap::new(width, height, pixels, 30.);
+        let mut rng = Rng::from_seed(0);
+
+        // Directions round trip through image coordinates.
+        let d = Vec3::new(0.3, -0.5, 0.8).unit_vector();
+        let (u, v) = map.direction_to_uv(&d);
+        assert!((map.uv_to_direction(u, v) - d).length() < 1e-9);
+
+        // The pdf integrates to 1 over the sphere.
+        let n = 200000;
+        let mut total = 0.;
+        for _ in 0..n {
+            total += map.pdf_value(&random_on_unit_sphere(&mut rng)) * 4. * PI;
+        }
+        assert!((total / n as f64 - 1.).abs() < 0.02, "{}", total / n as f64);
+
+        // About 90% of the weight is in the bright pixel.
+        let mut bright = 0;
+        for _ in 0..1000 {
+            let d = map.random(&mut rng);
+            assert!(map.pdf_value(&d) > 0.);
+            if map.value(&d).x > 1. {
+                bright += 1;
+            }
+        }
+        assert!(bright > 850, "{}", bright);
+    }
+}
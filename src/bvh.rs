@@ -3,16 +3,46 @@ use aabb::*;
 use util::*;
 use ray::*;
 use vec3::*;
-use std::cmp::Ordering;
 use std::f64;
+use std::fmt;
+use std::thread;
+use std::cell::Cell;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 
 /*
 Bounding Volume Hierarchy using AABB.
 
 Surface-Area-Heuristic (SAH)
-    When examining the bounding box to split, cut along the longest axis.
-    See http://psgraphics.blogspot.com/2016/03/a-simple-sah-bvh-build.html
+    Each node is split along the longest axis of the centers of its
+    objects' boxes.  Rather than trying every split, the objects are put
+    into a fixed number of equally sized bins along that axis, and the
+    split between bins with the lowest estimated cost is used.  The cost of
+    a split is the cost of traversing the node, plus the cost of
+    intersecting the objects on each side weighted by the chance that a ray
+    hitting the node hits that side (the ratio of the surface areas).  If
+    keeping the objects together in a leaf is cheaper, the node becomes a
+    leaf.  See "On fast Construction of SAH-based Bounding Volume
+    Hierarchies" (Wald 2007).
+
+The tree is stored flattened into an array in depth-first order (as in
+PBRT's LinearBVHNode), so the first child of a node is always the next
+node, and only the second child needs an index.  Traversal uses a small
+fixed stack, visits the child on the near side of the split first, and
+shrinks the search range as hits are found so that far nodes are skipped.
+
+Large nodes build their two children on separate threads, each into its own
+array, and the arrays are then joined in order.  The split decisions don't
+depend on the threads, so the tree is the same however many are used.
+
+Debugging
+    `BVH::stats` summarizes the shape of a tree, and `set_report_stats`
+    prints that summary for every tree as it is built.  Once
+    `set_count_traversal` turns it on, each thread also counts the boxes
+    and objects its rays are tested against (see `take_traversal_counts`),
+    which the "heatmap" integrator shows.  `render` only turns it on for
+    integrators that ask for it.  `HitableList` counts its objects
+    too, so scenes without a BVH show up as well.
 
 Consider trying a stackless algorithm:
 http://dcgi.felk.cvut.cz/publications/2011/hapala-sccg-esta
@@ -28,154 +58,722 @@ https://github.com/szellmann/visionaray/wiki/Acceleration-data-structures-and-tr
 
 */
 
-/// Dummy node is used in the case where the BVHNode has only 1 child (to
-/// avoid having to Clone the Hitable or using Option or Enum nodes).
-#[derive(Debug)]
-struct DummyNode {
-    bbox: AABB
+/// Deepest the tree can go.  Anything left at this depth goes into a single
+/// leaf, which keeps the traversal stack a fixed size.
+const MAX_DEPTH: usize = 64;
+
+/// Nodes with at least this many objects build their two children on
+/// separate threads (if there are threads to spare).  Below this, starting
+/// a thread costs more than it saves.
+const PARALLEL_BUILD_SIZE: usize = 4096;
+
+/// Whether to print the stats of every tree when it is built.
+static REPORT_STATS: AtomicBool = AtomicBool::new(false);
+
+/// Whether to keep `TRAVERSAL_COUNTS` up to date.
+static COUNT_TRAVERSAL: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// Box and object tests done by this thread since the last
+    /// `take_traversal_counts`.
+    static TRAVERSAL_COUNTS: Cell<TraversalCounts> = Cell::new(TraversalCounts::default());
 }
-impl Hitable for DummyNode {
-    #![allow(unused)]
-    fn hit(&self, rng: &mut Rng, r: &Ray<f64>, t_min: f64, t_max: f64) -> Option<HitRecord> { None }
-    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> { Some(self.bbox.clone()) }
+
+/// Print `BVH::stats` to stderr for every tree built from now on.
+pub fn set_report_stats(report: bool) {
+    REPORT_STATS.store(report, Ordering::Relaxed);
 }
-impl fmt::Display for DummyNode {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "DummyNode()")
+
+/// Count the tests done while looking for hits, for
+/// `take_traversal_counts`.  This is off by default, since it slows down
+/// every search a little.
+pub fn set_count_traversal(count: bool) {
+    COUNT_TRAVERSAL.store(count, Ordering::Relaxed);
+}
+
+/// Whether `set_count_traversal` is on.
+#[inline(always)]
+pub fn counting_traversal() -> bool {
+    COUNT_TRAVERSAL.load(Ordering::Relaxed)
+}
+
+/// Number of tests done while looking for hits in BVHs and lists.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct TraversalCounts {
+    /// Bounding boxes of nodes tested.
+    pub nodes: u64,
+    /// Objects in leaves and lists tested.  An object that is itself a BVH
+    /// or list (such as a mesh) counts once here, and its own tests are
+    /// counted too.
+    pub primitives: u64,
+}
+
+impl TraversalCounts {
+    /// Add to this thread's counts (if counting is on).
+    pub fn record(&self) {
+        if counting_traversal() {
+            TRAVERSAL_COUNTS.with(|total| {
+                let mut t = total.get();
+                t.nodes += self.nodes;
+                t.primitives += self.primitives;
+                total.set(t);
+            });
+        }
     }
 }
 
+/// The tests done by this thread since the last call, resetting the counts.
+pub fn take_traversal_counts() -> TraversalCounts {
+    TRAVERSAL_COUNTS.with(|counts| counts.replace(TraversalCounts::default()))
+}
 
-#[derive(Debug)]
-pub struct BVHNode {
-    left: Box<Hitable>,
-    right: Box<Hitable>,
+/// A node of the flattened tree (56 bytes).
+#[derive(Debug, Clone)]
+struct LinearNode {
     bbox: AABB,
+    /// For leaves, the index of the first object.  For interior nodes, the
+    /// index of the second child (the first child is the next node).
+    offset: u32,
+    /// Number of objects in a leaf, 0 for interior nodes.
+    count: u16,
+    /// Axis the children were split along (0=x, 1=y, 2=z).
+    axis: u8,
 }
 
-// Comparison function used for sorting elements along the longest axis.
-macro_rules! box_a_compare {
-    ($f:ident, $a:ident) => {
-        fn $f(a: &Box<Hitable>, b: &Box<Hitable>) -> Ordering {
-            // XXX: Should we pass in the time?
-            let box_left = a.bounding_box(0.0, 0.0).unwrap();
-            let box_right = b.bounding_box(0.0, 0.0).unwrap();
-            if box_left.min().$a - box_right.min().$a < 0.0 {
-                return Ordering::Less;
-            } else {
-                return Ordering::Greater;
+impl LinearNode {
+    /// Slab test using the precomputed inverse of the ray direction.
+    #[inline(always)]
+    fn hit(&self, origin: &Vec3<f64>, inv_direction: &Vec3<f64>, mut tmin: f64, mut tmax: f64) -> bool {
+        let min = self.bbox.min();
+        let max = self.bbox.max();
+        for a in 0..3 {
+            let t0 = (min[a] - origin[a]) * inv_direction[a];
+            let t1 = (max[a] - origin[a]) * inv_direction[a];
+            let (t0, t1) = if inv_direction[a] < 0. { (t1, t0) } else { (t0, t1) };
+            tmin = if t0 > tmin { t0 } else { tmin };
+            tmax = if t1 < tmax { t1 } else { tmax };
+            if tmax <= tmin {
+                return false;
             }
         }
+        true
     }
 }
 
-box_a_compare!(box_x_compare, x);
-box_a_compare!(box_y_compare, y);
-box_a_compare!(box_z_compare, z);
+/// Settings for building a `BVH`.
+#[derive(Debug, Clone)]
+pub struct BVHSettings {
+    /// Number of bins the objects are sorted into when looking for a split.
+    pub bins: usize,
+    /// Cost of traversing a node, relative to intersecting one object.
+    pub traversal_cost: f64,
+    /// Most objects a leaf may hold.  Leaves are only made this big when it
+    /// is cheaper than splitting.
+    pub max_leaf_size: usize,
+    /// Number of threads used to build the tree.  The tree is the same
+    /// whatever this is set to.
+    pub num_threads: usize,
+}
 
+impl Default for BVHSettings {
+    fn default() -> BVHSettings {
+        BVHSettings {
+            bins: 16,
+            traversal_cost: 0.5,
+            max_leaf_size: 4,
+            num_threads: thread::available_parallelism().map(|n| n.get()).unwrap_or(1),
+        }
+    }
+}
 
+#[derive(Debug)]
+pub struct BVH {
+    /// The root is first.
+    nodes: Vec<LinearNode>,
+    /// Objects in the order the leaves refer to them.  They are shared so
+    /// that the ones that emit can go in a scene's list of lights.
+    objects: Vec<Arc<Hitable>>,
+    /// Indexes into `objects` of the objects that emit.
+    lights: Vec<usize>,
+    /// From the settings, for working out the SAH cost in `stats`.
+    traversal_cost: f64,
+}
 
-impl BVHNode {
-    pub fn new(rng: &mut Rng, mut l: Vec<Box<Hitable>>, time0: f64, time1: f64) -> BVHNode
-    {
-        let n = l.len();
-        assert_ne!(n, 0);
+/// The name the tree had before it was flattened into an array.
+pub type BVHNode = BVH;
 
-        // Build a bounding box around all the elements.
-        let main_box = l.iter().fold(AABB::zero(), |bbox, ref x| {
-            let xbbox = x.bounding_box(time0, time1).unwrap();
-            surrounding_box(&xbbox, &bbox)
-        });
+/// Summary of a tree's shape, for judging how good it is.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BVHStats {
+    pub nodes: usize,
+    pub leaves: usize,
+    pub objects: usize,
+    /// Number of nodes from the root to the deepest leaf, inclusive.
+    pub depth: usize,
+    /// `leaf_sizes[n]` is the number of leaves holding `n` objects.
+    pub leaf_sizes: Vec<usize>,
+    /// Expected cost of finding the closest hit for a ray that hits the root
+    /// box, in units of one object test (see the module comment).
+    pub sah_cost: f64,
+}
 
-        // Sort the elements across the longest axis of the bounding box.
-        let axis = main_box.longest_axis();
-        match axis {
-            Axis::X => l.sort_by(box_x_compare),
-            Axis::Y => l.sort_by(box_y_compare),
-            Axis::Z => l.sort_by(box_z_compare),
-        }
-
-        // Cumulatively build the surface area of the bounding boxes.
-        let boxes: Vec<AABB> = l.iter().map(|h| h.bounding_box(time0, time1).unwrap()).collect();
-        let mut left_area = Vec::with_capacity(n);
-        let mut right_area = Vec::with_capacity(n);
-        left_area.push(boxes[0].area());
-        let mut left_box = boxes[0].clone();
-        for i in 1..n-1 {
-            left_box = surrounding_box(&left_box, &boxes[i]);
-            left_area.push(left_box.area());
-        }
-        // Technically, left_area[n-1] == 0, but it is not needed.
-
-        // Cumulatively build the surface area in reverse order.
-        // XXX: Is there a way to build this without needing to manually reverse at the end?
-        right_area.push(boxes[n-1].area());
-        let mut right_box = boxes[n-1].clone();
-        for i in (1..n-1).rev() {
-            right_box = surrounding_box(&right_box, &boxes[i]);
-            right_area.push(right_box.area());
-        }
-        right_area.push(0.0);
-        right_area.reverse();
-
-        // Find the index where to split the left/right trees. This is done by
-        // finding the minimum of the number_of_children*surface_area.
-        let mut min_sah = f64::MAX;
-        let mut min_sah_idx = 0;
-        for i in 0..n-1 {
-            let sah = i as f64*left_area[i] + (n-i-1) as f64*right_area[i+1];
-            if sah < min_sah {
-                min_sah_idx = i;
-                min_sah = sah;
+impl fmt::Display for BVHStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "BVH: {} objects, {} nodes, {} leaves, depth {}, SAH cost {:.2}",
+                 self.objects, self.nodes, self.leaves, self.depth, self.sah_cost)?;
+        write!(f, "  leaf sizes:")?;
+        for (size, &count) in self.leaf_sizes.iter().enumerate() {
+            if count > 0 {
+                write!(f, " {}x{}", count, size)?;
             }
         }
+        Ok(())
+    }
+}
 
-        // Build the left tree.
-        let mut rest = l.split_off(min_sah_idx+1);
-        let left;
-        if min_sah_idx == 0 {
-            left = l.remove(0);
-        } else {
-            left = Box::new(BVHNode::new(rng, l, time0, time1));
+/// An object waiting to be placed in the tree.
+struct BuildItem {
+    /// Taken when the object is put in a leaf.
+    object: Option<Arc<Hitable>>,
+    bbox: AABB,
+    centroid: Vec3<f64>,
+}
+
+/// Objects that fall in one bin.
+#[derive(Clone)]
+struct Bin {
+    bbox: Option<AABB>,
+    count: usize,
+}
+
+fn union(a: &Option<AABB>, b: &AABB) -> AABB {
+    match *a {
+        Some(ref a) => surrounding_box(a, b),
+        None => b.clone(),
+    }
+}
+
+impl BVH {
+    /// Build with the default settings.
+    pub fn new(l: Vec<Box<Hitable>>, time0: f64, time1: f64) -> BVH {
+        BVH::with_settings(l, time0, time1, &BVHSettings::default())
+    }
+
+    pub fn with_settings(l: Vec<Box<Hitable>>, time0: f64, time1: f64, settings: &BVHSettings) -> BVH {
+        assert_ne!(l.len(), 0);
+        assert!(settings.bins >= 2, "BVH needs at least 2 bins");
+        let mut items: Vec<BuildItem> = l.into_iter().map(|object| {
+            let bbox = object.bounding_box(time0, time1).expect("BVH objects must have a bounding box");
+            let centroid = 0.5 * (bbox.min() + bbox.max());
+            BuildItem { object: Some(Arc::from(object)), bbox: bbox, centroid: centroid }
+        }).collect();
+        let mut bvh = BVH::empty(items.len(), settings);
+        bvh.build(&mut items, settings, 0, settings.num_threads);
+        bvh.lights = (0..bvh.objects.len()).filter(|&i| bvh.objects[i].is_emissive()).collect();
+        if REPORT_STATS.load(Ordering::Relaxed) {
+            eprintln!("{}", bvh.stats());
+        }
+        bvh
+    }
+
+    fn empty(size: usize, settings: &BVHSettings) -> BVH {
+        BVH {
+            nodes: Vec::with_capacity(2 * size),
+            objects: Vec::with_capacity(size),
+            lights: Vec::new(),
+            traversal_cost: settings.traversal_cost,
         }
-        // Build the right tree.
-        let right;
-        if rest.len() == 1 {
-            right = rest.remove(0);
+    }
+
+    pub fn stats(&self) -> BVHStats {
+        let mut stats = BVHStats {
+            nodes: self.nodes.len(),
+            leaves: 0,
+            objects: self.objects.len(),
+            depth: 0,
+            leaf_sizes: Vec::new(),
+            sah_cost: 0.,
+        };
+        // A flat root box has no area to compare with, so count every node
+        // as always being hit.
+        let root_area = self.nodes[0].bbox.area();
+        let mut stack = vec![(0, 1)];
+        while let Some((index, depth)) = stack.pop() {
+            let node = &self.nodes[index];
+            let p = if root_area > 0. { node.bbox.area() / root_area } else { 1. };
+            if node.count > 0 {
+                let count = node.count as usize;
+                stats.leaves += 1;
+                stats.depth = stats.depth.max(depth);
+                if stats.leaf_sizes.len() <= count {
+                    stats.leaf_sizes.resize(count + 1, 0);
+                }
+                stats.leaf_sizes[count] += 1;
+                stats.sah_cost += p * count as f64;
+            } else {
+                stats.sah_cost += p * self.traversal_cost;
+                stack.push((index + 1, depth + 1));
+                stack.push((node.offset as usize, depth + 1));
+            }
+        }
+        stats
+    }
+
+    /// Add the nodes for `items` (and everything below), returning the
+    /// index of the new node.  Up to `threads` threads may be used.
+    fn build(&mut self,
+             items: &mut [BuildItem],
+             settings: &BVHSettings,
+             depth: usize,
+             threads: usize)
+             -> usize {
+        let n = items.len();
+        let main_box = items[1..].iter().fold(items[0].bbox.clone(), |bbox, item| {
+            surrounding_box(&item.bbox, &bbox)
+        });
+        let index = self.nodes.len();
+
+        let split = if depth + 1 >= MAX_DEPTH { None } else { find_split(items, &main_box, settings) };
+        let (axis, mid) = match split {
+            Some(split) => split,
+            None => {
+                assert!(n <= u16::max_value() as usize, "too many objects in a BVH leaf");
+                self.nodes.push(LinearNode {
+                    bbox: main_box,
+                    offset: self.objects.len() as u32,
+                    count: n as u16,
+                    axis: 0,
+                });
+                for item in items.iter_mut() {
+                    self.objects.push(item.object.take().unwrap());
+                }
+                return index;
+            },
+        };
+
+        // The node is filled in once the second child's index is known.
+        self.nodes.push(LinearNode { bbox: main_box, offset: 0, count: 0, axis: axis as u8 });
+        let (left, right) = items.split_at_mut(mid);
+        let second;
+        if threads > 1 && n >= PARALLEL_BUILD_SIZE {
+            // Build the children separately and then append them, which
+            // gives the same layout as building them one after the other.
+            let left_threads = threads / 2;
+            let (left_tree, right_tree) = thread::scope(|s| {
+                let handle = s.spawn(|| {
+                    let mut tree = BVH::empty(left.len(), settings);
+                    tree.build(left, settings, depth + 1, left_threads);
+                    tree
+                });
+                let mut tree = BVH::empty(right.len(), settings);
+                tree.build(right, settings, depth + 1, threads - left_threads);
+                (handle.join().unwrap(), tree)
+            });
+            self.append(left_tree);
+            second = self.nodes.len();
+            self.append(right_tree);
         } else {
-            right = Box::new(BVHNode::new(rng, rest, time0, time1));
+            self.build(left, settings, depth + 1, threads);
+            second = self.build(right, settings, depth + 1, threads);
+        }
+        self.nodes[index].offset = second as u32;
+        index
+    }
+
+    /// Sum of `pdf_value` over the objects whose boxes the ray from `o`
+    /// along `v` passes through.  The others are assumed to have a pdf of
+    /// zero in that direction, which is true of objects that only sample
+    /// points on their own surface.
+    pub fn pdf_value_sum(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        let inv_direction = Vec3::new(1. / v.x, 1. / v.y, 1. / v.z);
+        let mut sum = 0.;
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut stack_len = 1;
+        while stack_len > 0 {
+            stack_len -= 1;
+            let index = stack[stack_len];
+            let node = &self.nodes[index];
+            if !node.hit(o, &inv_direction, 0., f64::MAX) {
+                continue;
+            }
+            if node.count > 0 {
+                let first = node.offset as usize;
+                for object in &self.objects[first..first + node.count as usize] {
+                    sum += object.pdf_value(rng, o, v);
+                }
+            } else {
+                stack[stack_len] = index + 1;
+                stack[stack_len + 1] = node.offset as usize;
+                stack_len += 2;
+            }
+        }
+        sum
+    }
+
+    /// Add the nodes and objects of a separately built subtree to the end.
+    fn append(&mut self, tree: BVH) {
+        let node_base = self.nodes.len() as u32;
+        let object_base = self.objects.len() as u32;
+        self.nodes.extend(tree.nodes.into_iter().map(|mut node| {
+            if node.count > 0 {
+                node.offset += object_base;
+            } else {
+                node.offset += node_base;
+            }
+            node
+        }));
+        self.objects.extend(tree.objects);
+    }
+}
+
+/// Decide how to split `items`, reordering them so the first part goes in
+/// the first child.  Returns the axis and the size of the first part, or
+/// None if they should stay together in a leaf.
+fn find_split(items: &mut [BuildItem], main_box: &AABB, settings: &BVHSettings) -> Option<(usize, usize)> {
+    let n = items.len();
+    if n == 1 {
+        return None;
+    }
+    let centroid_box = items[1..].iter().fold(AABB::new(items[0].centroid, items[0].centroid), |bbox, item| {
+        surrounding_box(&bbox, &AABB::new(item.centroid, item.centroid))
+    });
+    let axis = match centroid_box.longest_axis() {
+        Axis::X => 0,
+        Axis::Y => 1,
+        Axis::Z => 2,
+    };
+    // For when there is nothing to choose between: split in the middle if
+    // the items don't fit in a leaf.
+    let middle = if n <= settings.max_leaf_size { None } else { Some((axis, n / 2)) };
+    let cmin = centroid_box.min()[axis];
+    let extent = centroid_box.max()[axis] - cmin;
+    if !(extent > 0.) {
+        // All the centers are in the same place.
+        return middle;
+    }
+
+    let nbins = settings.bins;
+    let bin_index = |item: &BuildItem| {
+        (((item.centroid[axis] - cmin) / extent * nbins as f64) as usize).min(nbins - 1)
+    };
+    let mut bins = vec![Bin { bbox: None, count: 0 }; nbins];
+    for item in items.iter() {
+        let bin = &mut bins[bin_index(item)];
+        bin.bbox = Some(union(&bin.bbox, &item.bbox));
+        bin.count += 1;
+    }
+
+    // Area and count of everything right of each split, then sweep from
+    // the left to find the cheapest split.
+    let mut right_area = vec![0.; nbins];
+    let mut right_count = vec![0; nbins];
+    let mut right_box: Option<AABB> = None;
+    let mut count = 0;
+    for i in (1..nbins).rev() {
+        if let Some(ref bbox) = bins[i].bbox {
+            right_box = Some(union(&right_box, bbox));
+        }
+        count += bins[i].count;
+        right_area[i] = right_box.as_ref().map_or(0., |b| b.area());
+        right_count[i] = count;
+    }
+    let area = main_box.area();
+    let mut best: Option<(f64, usize)> = None;
+    let mut left_box: Option<AABB> = None;
+    let mut left_count = 0;
+    for i in 0..nbins-1 {
+        if let Some(ref bbox) = bins[i].bbox {
+            left_box = Some(union(&left_box, bbox));
+        }
+        left_count += bins[i].count;
+        if left_count == 0 || right_count[i+1] == 0 {
+            continue;
         }
-        BVHNode{
-            left: left,
-            right: right,
-            bbox: main_box,
+        let left_area = left_box.as_ref().unwrap().area();
+        let cost = settings.traversal_cost +
+            (left_count as f64 * left_area + right_count[i+1] as f64 * right_area[i+1]) / area;
+        if best.map_or(true, |(best_cost, _)| cost < best_cost) {
+            best = Some((cost, i));
         }
     }
+
+    let (cost, split_bin) = match best {
+        Some(best) => best,
+        // Infinite or NaN boxes can put every item in the same bin.
+        None => return middle,
+    };
+    // Intersecting every object in a leaf costs 1 each.  Flat boxes have no
+    // area, in which case the cost says nothing and splitting is safest.
+    if n <= settings.max_leaf_size && area > 0. && n as f64 <= cost {
+        return None;
+    }
+
+    // Move the items in the first half to the front.
+    let mut mid = 0;
+    for i in 0..n {
+        if bin_index(&items[i]) <= split_bin {
+            items.swap(i, mid);
+            mid += 1;
+        }
+    }
+    Some((axis, mid))
 }
 
-impl Hitable for BVHNode {
+impl Hitable for BVH {
     fn hit(&self, rng: &mut Rng, r: &Ray<f64>, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        if self.bbox.hit(rng, r, t_min, t_max) {
-            let hit_left = self.left.hit(rng, r, t_min, t_max);
-            let hit_right = self.right.hit(rng, r, t_min, t_max);
-            match (hit_left, hit_right) {
-                (Some(left), Some(right)) => {
-                    if left.t < right.t {
-                        return Some(left);
-                    } else {
-                        return Some(right);
+        let origin = r.origin();
+        let direction = r.direction();
+        let inv_direction = Vec3::new(1. / direction.x, 1. / direction.y, 1. / direction.z);
+        let dir_is_neg = [direction.x < 0., direction.y < 0., direction.z < 0.];
+        let mut closest = t_max;
+        let mut result = None;
+        let mut counts = TraversalCounts::default();
+        // Nodes still to visit.
+        let mut stack = [0usize; MAX_DEPTH];
+        let mut stack_len = 0;
+        let mut index = 0;
+        loop {
+            let node = &self.nodes[index];
+            counts.nodes += 1;
+            if node.hit(&origin, &inv_direction, t_min, closest) {
+                if node.count > 0 {
+                    let first = node.offset as usize;
+                    counts.primitives += node.count as u64;
+                    for object in &self.objects[first..first + node.count as usize] {
+                        if let Some(rec) = object.hit(rng, r, t_min, closest) {
+                            closest = rec.t;
+                            result = Some(rec);
+                        }
                     }
-                },
-                (Some(left), None) => { return Some(left); },
-                (None, Some(right)) => { return Some(right); },
-                _ => { return None; }
+                } else {
+                    // Visit the child on the near side of the split first, so
+                    // that hits there can rule out the far side.
+                    let (near, far) = if dir_is_neg[node.axis as usize] {
+                        (node.offset as usize, index + 1)
+                    } else {
+                        (index + 1, node.offset as usize)
+                    };
+                    stack[stack_len] = far;
+                    stack_len += 1;
+                    index = near;
+                    continue;
+                }
             }
+            if stack_len == 0 {
+                break;
+            }
+            stack_len -= 1;
+            index = stack[stack_len];
         }
-        return None;
+        counts.record();
+        result
     }
 
     fn bounding_box(&self, _: f64, _: f64) -> Option<AABB> {
-        Some(self.bbox.clone())
+        Some(self.nodes[0].bbox.clone())
+    }
+
+    // Only the objects that emit are sampled (all of them if none do, for a
+    // BVH added with `SceneBuilder::add_sampled`), each picked equally
+    // often.  Every one is asked for its pdf, since some objects sample
+    // directions outside their bounding box (like `MovingSphere`), so this
+    // takes time in proportion to the number of lights.  Scenes normally
+    // sample the lights directly instead (see `add_lights`).
+    fn pdf_value(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        if self.lights.is_empty() {
+            let sum: f64 = self.objects.iter().map(|object| object.pdf_value(rng, o, v)).sum();
+            sum / self.objects.len() as f64
+        } else {
+            let sum: f64 = self.lights.iter().map(|&i| self.objects[i].pdf_value(rng, o, v)).sum();
+            sum / self.lights.len() as f64
+        }
+    }
+
+    fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> {
+        if self.lights.is_empty() {
+            let index = ((rng.rand64() * self.objects.len() as f64) as usize).min(self.objects.len() - 1);
+            self.objects[index].random(rng, o)
+        } else {
+            let index = ((rng.rand64() * self.lights.len() as f64) as usize).min(self.lights.len() - 1);
+            self.objects[self.lights[index]].random(rng, o)
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        !self.lights.is_empty()
+    }
+
+    fn add_lights(&self, lights: &mut Vec<Arc<Hitable>>) -> bool {
+        for &i in &self.lights {
+            let object = &self.objects[i];
+            if !object.add_lights(lights) {
+                lights.push(object.clone());
+            }
+        }
+        true
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sphere::*;
+    use hitable_list::*;
+    use material::*;
+    use texture::*;
+    use pdf::random_on_unit_sphere;
+
+    #[test]
+    fn test_bvh() {
+        let mut rng = Rng::from_seed(0);
+        let m: Arc<Material> = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.5, 0.5, 0.5)))));
+        let spheres: Vec<(Vec3<f64>, f64)> = (0..200).map(|_| {
+            (10. * Vec3::new(rng.rand64() - 0.5, rng.rand64() - 0.5, rng.rand64() - 0.5), 0.1 + rng.rand64())
+        }).collect();
+        let objects = || -> Vec<Box<Hitable>> {
+            spheres.iter().map(|&(center, radius)| Box::new(Sphere::new(center, radius, m.clone())) as Box<Hitable>).collect()
+        };
+        let mut list = HitableList::new();
+        for object in objects() {
+            list.add_boxed(object);
+        }
+        let bvh = BVH::new(objects(), 0., 1.);
+        assert_eq!(bvh.objects.len(), 200);
+        assert!(bvh.nodes.iter().all(|node| node.count as usize <= 4));
+        let settings = BVHSettings { bins: 4, traversal_cost: 1., max_leaf_size: 1, num_threads: 1 };
+        let single = BVH::with_settings(objects(), 0., 1., &settings);
+        assert_eq!(single.nodes.len(), 2 * 200 - 1);
+
+        let stats = single.stats();
+        assert_eq!((stats.nodes, stats.leaves, stats.objects), (399, 200, 200));
+        assert_eq!(stats.leaf_sizes, vec![0, 200]);
+        assert!(stats.depth >= 9 && stats.depth < MAX_DEPTH, "{}", stats.depth);
+        let stats = bvh.stats();
+        assert_eq!(stats.leaf_sizes.iter().sum::<usize>(), stats.leaves);
+        assert_eq!(stats.nodes, 2 * stats.leaves - 1);
+        // Somewhere between testing the root box and testing every object.
+        assert!(stats.sah_cost > 0.5 && stats.sah_cost < 200., "{}", stats.sah_cost);
+
+        // The closest hit is the same as testing every object.
+        let mut hits = 0;
+        for _ in 0..2000 {
+            let origin = 8. * random_on_unit_sphere(&mut rng);
+            let r = Ray::new(origin, random_on_unit_sphere(&mut rng));
+            let expected = list.hit(&mut rng, &r, 0.001, f64::MAX).map(|rec| rec.t);
+            assert_eq!(bvh.hit(&mut rng, &r, 0.001, f64::MAX).map(|rec| rec.t), expected);
+            assert_eq!(single.hit(&mut rng, &r, 0.001, f64::MAX).map(|rec| rec.t), expected);
+            if expected.is_some() {
+                hits += 1;
+            }
+        }
+        assert!(hits > 200, "{}", hits);
+
+        set_count_traversal(true);
+        take_traversal_counts();
+        // Aimed at a sphere, so at least one leaf is reached.
+        let r = Ray::new(spheres[0].0 + Vec3::new(0., 0., 20.), Vec3::new(0., 0., -1.));
+        single.hit(&mut rng, &r, 0.001, f64::MAX);
+        let counts = take_traversal_counts();
+        assert!(counts.nodes >= 1 && counts.nodes < 399, "{:?}", counts);
+        assert!(counts.primitives >= 1 && counts.primitives < 200, "{:?}", counts);
+        assert_eq!(take_traversal_counts(), TraversalCounts::default());
+    }
+
+    #[test]
+    fn test_light_sampling() {
+        use aarect::*;
+        use moving_sphere::*;
+        use scene::*;
+        use camera::*;
+        use output::*;
+
+        let light: Arc<Material> = Arc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(4., 4., 4.)))));
+        let lights = || -> Vec<Box<Hitable>> {
+            vec![Box::new(XZRect::new(-1., 1., -1., 1., 3., light.clone())),
+                 Box::new(XYRect::new(2., 3., 0., 1., -2., light.clone())),
+                 Box::new(MovingSphere::new(Vec3::new(-2., 1., 0.), Vec3::new(-2., 1.5, 0.5), 0., 1., 0.3, light.clone()))]
+        };
+        let mut rng = Rng::from_seed(0);
+        let bvh = BVH::new(lights(), 0., 1.);
+        check_sampling(&bvh, &Vec3::new(0., 0.5, 0.));
+
+        // Added through the scene builder, the BVH samples the same as
+        // listing the lights by hand.
+        let mut builder = SceneBuilder::new();
+        builder.add_hitable(bvh);
+        let camera = Camera::new(Vec3::new(0., 0., 5.), Vec3::zero(), Vec3::new(0., 1., 0.),
+                                 20., 1., 0., 5., 0., 1.);
+        let output = OutputSettings {
+            format: OutputFormat::Png,
+            filename_template: String::from("test.png"),
+            width: 1,
+            height: 1,
+            display: DisplaySettings::default(),
+            template_values: TemplateValues::default(),
+        };
+        let scene = builder.build(camera, 1, output);
+        let mut list = HitableList::new();
+        for object in lights() {
+            list.add_boxed(object);
+        }
+        let o = Vec3::new(0.5, 0.5, 0.5);
+        for _ in 0..1000 {
+            let v = scene.light_shapes.random(&mut rng, &o);
+            let expected = list.pdf_value(&mut rng, &o, &v);
+            assert!(expected > 0.);
+            assert!((scene.light_shapes.pdf_value(&mut rng, &o, &v) - expected).abs() < 1e-9 * expected);
+        }
+    }
+
+    #[test]
+    fn test_degenerate_boxes() {
+        // Infinite boxes, or centers so far apart that the distance between
+        // them overflows, must not stop the tree being built.
+        let m: Arc<Material> = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.5, 0.5, 0.5)))));
+        let far: Vec<(Vec3<f64>, f64)> = (0..20).map(|i| {
+            (Vec3::new(if i % 2 == 0 { -1e308 } else { 1e308 }, 0., 0.), 1.)
+        }).collect();
+        let infinite: Vec<(Vec3<f64>, f64)> = (0..20).map(|i| {
+            (Vec3::new(i as f64, 0., 0.), if i % 7 == 0 { f64::INFINITY } else { 0.5 })
+        }).collect();
+        for spheres in &[far, infinite] {
+            for &max_leaf_size in &[1, 4] {
+                let objects = spheres.iter().map(|&(center, radius)| {
+                    Box::new(Sphere::new(center, radius, m.clone())) as Box<Hitable>
+                }).collect();
+                let settings = BVHSettings { max_leaf_size: max_leaf_size, ..BVHSettings::default() };
+                let bvh = BVH::with_settings(objects, 0., 1., &settings);
+                assert_eq!(bvh.objects.len(), 20);
+                assert!(bvh.nodes.iter().all(|node| node.count as usize <= max_leaf_size));
+            }
+        }
+    }
+
+    #[test]
+    fn test_parallel_build() {
+        let mut rng = Rng::from_seed(0);
+        let m: Arc<Material> = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.5, 0.5, 0.5)))));
+        let spheres: Vec<(Vec3<f64>, f64)> = (0..20000).map(|_| {
+            (100. * Vec3::new(rng.rand64(), rng.rand64(), rng.rand64()), 0.01 + 0.5 * rng.rand64())
+        }).collect();
+        let build = |num_threads| {
+            let objects = spheres.iter().map(|&(center, radius)| {
+                Box::new(Sphere::new(center, radius, m.clone())) as Box<Hitable>
+            }).collect();
+            let settings = BVHSettings { num_threads: num_threads, ..BVHSettings::default() };
+            BVH::with_settings(objects, 0., 1., &settings)
+        };
+        let serial = build(1);
+        let parallel = build(4);
+        assert_eq!(serial.nodes.len(), parallel.nodes.len());
+        for (a, b) in serial.nodes.iter().zip(parallel.nodes.iter()) {
+            assert_eq!((a.offset, a.count, a.axis), (b.offset, b.count, b.axis));
+            assert_eq!((a.bbox.min(), a.bbox.max()), (b.bbox.min(), b.bbox.max()));
+        }
+        for (a, b) in serial.objects.iter().zip(parallel.objects.iter()) {
+            let a = a.bounding_box(0., 1.).unwrap();
+            let b = b.bounding_box(0., 1.).unwrap();
+            assert_eq!((a.min(), a.max()), (b.min(), b.max()));
+        }
+    }
+}
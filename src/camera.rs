@@ -80,3 +80,135 @@ impl Camera {
                       time)
     }
 }
+
+/// Camera parameters at one frame of an animation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CameraKey {
+    pub frame: u32,
+    pub lookfrom: Vec3<f64>,
+    pub lookat: Vec3<f64>,
+    /// Vertical field of view in degrees.
+    pub vfov: f64,
+    pub aperture: f64,
+    pub focus_dist: f64,
+}
+
+impl CameraKey {
+    fn lerp(&self, other: &CameraKey, t: f64) -> CameraKey {
+        let lerp = |a: f64, b: f64| a + t * (b - a);
+        CameraKey {
+            frame: self.frame,
+            lookfrom: self.lookfrom + t * (other.lookfrom - self.lookfrom),
+            lookat: self.lookat + t * (other.lookat - self.lookat),
+            vfov: lerp(self.vfov, other.vfov),
+            aperture: lerp(self.aperture, other.aperture),
+            focus_dist: lerp(self.focus_dist, other.focus_dist),
+        }
+    }
+}
+
+/// A camera that moves over a sequence of frames.
+///
+/// Parameters are interpolated linearly between keyframes, and hold their
+/// values before the first and after the last key.
+///
+/// The shutter moves along with the frames: `first_frame` is exposed from
+/// `time0` to `time1`, and every frame after it is `frame_duration` later.
+/// Moving objects therefore keep moving at the same speed through the
+/// sequence.
+#[derive(Debug, Clone)]
+pub struct CameraAnimation {
+    /// Sorted by frame.
+    keys: Vec<CameraKey>,
+    vup: Vec3<f64>,
+    aspect: f64,
+    first_frame: u32,
+    time0: f64,
+    time1: f64,
+    frame_duration: f64,
+}
+
+impl CameraAnimation {
+    /// Panics if there are no keys.
+    pub fn new(mut keys: Vec<CameraKey>,
+               vup: Vec3<f64>,
+               aspect: f64,
+               first_frame: u32,
+               time0: f64,
+               time1: f64,
+               frame_duration: f64)
+               -> CameraAnimation {
+        assert!(!keys.is_empty(), "camera animation needs at least one key");
+        keys.sort_by_key(|k| k.frame);
+        CameraAnimation {
+            keys: keys,
+            vup: vup,
+            aspect: aspect,
+            first_frame: first_frame,
+            time0: time0,
+            time1: time1,
+            frame_duration: frame_duration,
+        }
+    }
+
+    /// Shutter open and close times for a frame.
+    pub fn shutter(&self, frame: u32) -> (f64, f64) {
+        let offset = (frame as f64 - self.first_frame as f64) * self.frame_duration;
+        (self.time0 + offset, self.time1 + offset)
+    }
+
+    /// The interpolated camera parameters at a frame.
+    pub fn key(&self, frame: u32) -> CameraKey {
+        let next = self.keys.iter().position(|k| k.frame > frame);
+        let mut key = match next {
+            Some(0) => self.keys[0].clone(),
+            None => self.keys[self.keys.len() - 1].clone(),
+            Some(i) => {
+                let (k0, k1) = (&self.keys[i - 1], &self.keys[i]);
+                let t = (frame - k0.frame) as f64 / (k1.frame - k0.frame) as f64;
+                k0.lerp(k1, t)
+            },
+        };
+        key.frame = frame;
+        key
+    }
+
+    pub fn camera(&self, frame: u32) -> Camera {
+        let key = self.key(frame);
+        let (time0, time1) = self.shutter(frame);
+        Camera::new(key.lookfrom,
+                    key.lookat,
+                    self.vup,
+                    key.vfov,
+                    self.aspect,
+                    key.aperture,
+                    key.focus_dist,
+                    time0,
+                    time1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_camera_animation() {
+        let key = |frame, x, vfov| CameraKey {
+            frame: frame,
+            lookfrom: Vec3::new(x, 0., 0.),
+            lookat: Vec3::new(x, 0., -1.),
+            vfov: vfov,
+            aperture: 0.,
+            focus_dist: 1.,
+        };
+        let animation = CameraAnimation::new(vec![key(20, 10., 40.), key(10, 0., 90.)],
+                                             Vec3::new(0., 1., 0.), 1., 1, 0., 0.5, 1.);
+        assert_eq!(animation.key(1), key(1, 0., 90.));
+        assert_eq!(animation.key(10), key(10, 0., 90.));
+        assert_eq!(animation.key(15), key(15, 5., 65.));
+        assert_eq!(animation.key(30), key(30, 10., 40.));
+        assert_eq!(animation.shutter(1), (0., 0.5));
+        assert_eq!(animation.shutter(4), (3., 3.5));
+    }
+}
@@ -7,11 +7,13 @@ use ray::*;
 use vec3::*;
 use aabb::*;
 
+/// A volume of fog or smoke with constant density, filling the inside of a
+/// closed, convex boundary.
 #[derive(Debug)]
 pub struct ConstantMedium {
     boundary: Box<Hitable>,
     density: f64,
-    phase_function: Rc<Material>,
+    phase_function: Arc<Material>,
 }
 
 impl ConstantMedium {
@@ -19,13 +21,17 @@ impl ConstantMedium {
         ConstantMedium {
             boundary: boundary,
             density: density,
-            phase_function: Rc::new(Isotropic::new(albedo))
+            phase_function: Arc::new(Isotropic::new(albedo))
         }
     }
 }
 
 impl Hitable for ConstantMedium {
     fn hit(&self, rng: &mut Rng, r: &Ray<f64>, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // Find where the line enters and leaves the boundary, even if that
+        // is behind the ray.  A ray that starts inside the medium (such as
+        // one scattered by it) enters behind its origin, so the part inside
+        // starts at t_min.
         if let Some(mut rec1) = self.boundary.hit(rng, r, -f64::MAX, f64::MAX) {
             if let Some(mut rec2) = self.boundary.hit(rng, r, rec1.t+0.0001, f64::MAX) {
                 if rec1.t < t_min {
@@ -37,9 +43,6 @@ impl Hitable for ConstantMedium {
                 if rec1.t >= rec2.t {
                     return None;
                 }
-                if rec1.t < 0. {
-                    rec1.t = 0.;
-                }
                 let distance_inside_boundary = (rec2.t - rec1.t)*r.direction().length();
                 let hit_distance = -(1./self.density)*rng.rand64().ln();
                 if hit_distance < distance_inside_boundary {
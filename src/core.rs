@@ -2,11 +2,15 @@ use vec3::*;
 use ray::*;
 use scene::*;
 use util::*;
-use pdf::*;
 use output::*;
 use output;
 // use std::error::Error;
 use perlin::*;
+use bvh::set_count_traversal;
+use std::cmp;
+use std::sync::{mpsc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
 
 #[inline(always)]
 fn de_nan(c: &Vec3<f64>) -> Vec3<f64> {
@@ -16,67 +20,196 @@ fn de_nan(c: &Vec3<f64>) -> Vec3<f64> {
     return Vec3::new(x, y, z);
 }
 
-/// Get color for ray r cast into scene.
-///
-/// A miss into the background is a linear gradient from white to blue.
-fn color(rng: &mut Rng, r: &Ray<f64>, scene: &Scene, depth: u8) -> Vec3<f64> {
-    // Use 0.0001 to ignore hits very near zero (the ray should travel at
-    // least some distance).
-    if let Some(hrec) = scene.world.hit(rng, r, 0.0001, f64::MAX) {
-        let emitted = hrec.material.emitted(r, &hrec, hrec.u, hrec.v, &hrec.p);
-        if depth < 50 {
-            if let Some(srec) = hrec.material.scatter(rng, r, &hrec) {
-                if let Some(specular_ray) = srec.specular_ray {
-                    return srec.attenuation * color(rng, &specular_ray, scene, depth+1);
-                } else {
-                    let plight = HitablePdf::new(hrec.p, &*scene.light_shapes);
-                    let spdf = srec.pdf.unwrap();
-                    let p = MixturePdf::new(&plight, &*spdf);
-                    let scattered = Ray::new_time(hrec.p, p.generate(rng), r.time());
-                    let pdf_val = p.value(rng, &scattered.direction());
-                    return emitted + srec.attenuation*hrec.material.scattering_pdf(r, &hrec, &scattered)*
-                        color(rng, &scattered, scene, depth + 1) / pdf_val;
-                }
-            } else {
-                return emitted;
-            }
+/// Messages sent from the render workers back to the thread that owns the
+/// output.  Buckets are referred to by index into the bucket list.
+enum BucketMsg {
+    Started(usize),
+    Finished(usize, Vec<Vec<Vec3<f64>>>),
+}
+
+/// Running totals of the samples taken for a single pixel.
+#[derive(Debug, Clone, Default)]
+struct PixelStats {
+    /// Sum of all samples.
+    sum: Vec3<f64>,
+    /// Sum of the luminance of the samples (for estimating variance).
+    lum_sum: f64,
+    /// Sum of the squared luminance of the samples.
+    lum_sq_sum: f64,
+    /// Number of samples taken.
+    samples: u32,
+}
+
+impl PixelStats {
+    fn add(&mut self, col: &Vec3<f64>) {
+        let lum = luminance(col);
+        self.sum += *col;
+        self.lum_sum += lum;
+        self.lum_sq_sum += lum * lum;
+        self.samples += 1;
+    }
+
+    fn mean(&self) -> Vec3<f64> {
+        if self.samples == 0 {
+            return Vec3::zero();
         }
-        return emitted;
-    } else {
-        // Hit background.
-        // let unit_direction = r.direction().unit_vector();
-        // let t = 0.5 * (unit_direction.y + 1.0);
-        // return (1.0 - t) * Vec3::new(1.0, 1.0, 1.0) + t * Vec3::new(0.5, 0.7, 1.0);
-        return Vec3::zero();
+        self.sum / self.samples as f64
+    }
+
+    /// Estimated standard error of the mean luminance, relative to the mean.
+    ///
+    /// Very dark pixels are compared against a small floor instead of their
+    /// mean, otherwise they would never be considered converged.
+    fn relative_error(&self) -> f64 {
+        if self.samples < 2 {
+            return f64::INFINITY;
+        }
+        let n = self.samples as f64;
+        let mean = self.lum_sum / n;
+        let variance = ((self.lum_sq_sum - self.lum_sum * mean) / (n - 1.)).max(0.);
+        let std_error = (variance / n).sqrt();
+        return std_error / mean.max(0.01);
+    }
+
+    /// Whether an adaptive render is done with this pixel.
+    fn converged(&self, adaptive: &AdaptiveSettings) -> bool {
+        self.samples >= adaptive.max_samples ||
+            (self.samples >= adaptive.min_samples &&
+             self.relative_error() < adaptive.noise_threshold)
     }
 }
 
 pub fn render(scene: &Scene, output_settings: &OutputSettings, output: &mut Box<Output>) -> output::Result<()> {
-    perlin_init();
+    // Counting slows down every search, so it is only on while an
+    // integrator that uses it is rendering.
+    set_count_traversal(scene.integrator.counts_traversal());
+    let result = render_passes(scene, output_settings, output);
+    set_count_traversal(false);
+    result
+}
+
+fn render_passes(scene: &Scene, output_settings: &OutputSettings, output: &mut Box<Output>) -> output::Result<()> {
+    perlin_init(scene.render_settings.seed);
     output.begin()?;
-    let mut rng = Rng::new();
     let width = output_settings.width;
     let height = output_settings.height;
-    let ns = scene.num_samples;
+    let ns = match scene.render_settings.adaptive {
+        Some(ref adaptive) => adaptive.max_samples,
+        None => scene.num_samples,
+    };
     let buckets = output::create_buckets(width, height, output::BucketStrategy::Spiral, 64, 64);
-    for bucket in buckets {
-        output.begin_bucket(&bucket)?;
-        render_bucket(&mut rng, scene, output, ns, width, height, bucket)?;
+    // Without progressive rendering, everything is done in a single pass.
+    let pass_samples = match scene.render_settings.progressive_samples {
+        Some(n) => cmp::max(n, 1),
+        None => ns,
+    };
+    // Samples accumulated so far for every pixel, grouped by bucket.  Only
+    // one worker touches a bucket at a time, the lock just makes that
+    // visible to the compiler.
+    let stats: Vec<Mutex<Vec<PixelStats>>> = buckets.iter().map(|bucket| {
+        Mutex::new(vec![PixelStats::default(); (bucket.width*bucket.height) as usize])
+    }).collect();
+    let mut samples_done = 0;
+    let mut pass = 0;
+    while samples_done < ns {
+        let samples = cmp::min(pass_samples, ns - samples_done);
+        render_pass(scene, output_settings, output, &buckets, &stats, samples)?;
+        samples_done += samples;
+        output.end_pass(pass)?;
+        pass += 1;
+        // Stop early once an adaptive render has no pixels left to sample.
+        if let Some(ref adaptive) = scene.render_settings.adaptive {
+            let converged = stats.iter().all(|bucket_stats| {
+                bucket_stats.lock().unwrap().iter().all(|pixel| pixel.converged(adaptive))
+            });
+            if converged {
+                break;
+            }
+        }
     }
 
     output.end()?;
     Ok(())
 }
 
-fn render_bucket(rng: &mut Rng,
-                 scene: &Scene,
-                 output: &mut Box<Output>,
+/// Render every bucket once with up to `samples` more samples per pixel.
+fn render_pass(scene: &Scene,
+               output_settings: &OutputSettings,
+               output: &mut Box<Output>,
+               buckets: &Vec<output::Bucket>,
+               stats: &Vec<Mutex<Vec<PixelStats>>>,
+               samples: u32)
+               -> output::Result<()>
+{
+    let width = output_settings.width;
+    let height = output_settings.height;
+    let num_threads = cmp::max(scene.render_settings.num_threads, 1);
+    // Each worker grabs the next bucket from the list until it is empty.
+    let next_bucket = AtomicUsize::new(0);
+    // Set when the output fails (or the user asks to exit) so the workers
+    // stop picking up new buckets.
+    let cancelled = AtomicBool::new(false);
+    let (tx, rx) = mpsc::channel();
+
+    thread::scope(|s| {
+        for _ in 0..num_threads {
+            let tx = tx.clone();
+            let next_bucket = &next_bucket;
+            let cancelled = &cancelled;
+            s.spawn(move || {
+                while !cancelled.load(Ordering::Relaxed) {
+                    let index = next_bucket.fetch_add(1, Ordering::SeqCst);
+                    if index >= buckets.len() {
+                        break;
+                    }
+                    if tx.send(BucketMsg::Started(index)).is_err() {
+                        break;
+                    }
+                    let mut bucket_stats = stats[index].lock().unwrap();
+                    let pixels = render_bucket(scene, samples, width, height,
+                                               &buckets[index], &mut bucket_stats);
+                    if tx.send(BucketMsg::Finished(index, pixels)).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        // Only the workers hold senders now, so the loop below ends when
+        // they have all finished.
+        drop(tx);
+
+        // The output stays on this thread (the GUI window can't be shared),
+        // and receives buckets in the order they complete.
+        for msg in rx.iter() {
+            let result = match msg {
+                BucketMsg::Started(index) => output.begin_bucket(&buckets[index]),
+                BucketMsg::Finished(index, pixels) => output.put_bucket(&buckets[index], &pixels),
+            };
+            if result.is_err() {
+                cancelled.store(true, Ordering::Relaxed);
+                return result;
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Take up to `ns` more samples for every pixel in the bucket, adding them
+/// to `stats`.  With adaptive sampling, pixels stop early once they are
+/// below the noise threshold.
+///
+/// Returns the average of all samples taken so far for each pixel (linear,
+/// the outputs take care of any conversion for display).
+fn render_bucket(scene: &Scene,
                  ns: u32,
                  width: u32,
                  height: u32,
-                 bucket: output::Bucket)
-                 -> output::Result<()>
+                 bucket: &output::Bucket,
+                 stats: &mut Vec<PixelStats>)
+                 -> Vec<Vec<Vec3<f64>>>
 {
+    let adaptive = scene.render_settings.adaptive.as_ref();
+    let seed = scene.render_settings.seed;
     let mut pixels = Vec::with_capacity(bucket.height as usize);
     for bucket_j in 0..bucket.height {
         let mut pixel_row = Vec::with_capacity(bucket.width as usize);
@@ -85,23 +218,27 @@ fn render_bucket(rng: &mut Rng,
         let j = height - (bucket_j + bucket.y);
         for bucket_i in 0..bucket.width {
             let i = bucket_i + bucket.x;
-            let mut col = Vec3::<f64>::zero();
-            for _ in 0..ns {
+            let pixel = &mut stats[(bucket_j*bucket.width + bucket_i) as usize];
+            let mut target = pixel.samples + ns;
+            if let Some(adaptive) = adaptive {
+                target = cmp::min(target, adaptive.max_samples);
+            }
+            while pixel.samples < target {
+                if let Some(adaptive) = adaptive {
+                    if pixel.converged(adaptive) {
+                        break;
+                    }
+                }
+                let mut rng = Rng::for_sample(seed, i, j, pixel.samples);
                 let u = (i as f64 + rng.rand64()) / width as f64;
                 let v = (j as f64 + rng.rand64()) / height as f64;
-                let r = scene.camera.get_ray(rng, u, v);
+                let r = scene.camera.get_ray(&mut rng, u, v);
 
-                col += de_nan(&color(rng, &r, scene, 0));
+                pixel.add(&de_nan(&scene.integrator.radiance(&mut rng, &r, scene)));
             }
-            col /= ns as f64;
-            // Poor-man's gamma correction.
-            col = Vec3::new(col.x.sqrt(), col.y.sqrt(), col.z.sqrt());
-            pixel_row.push(col);
-            // output.put_pixel(i, height-j-1, &col)?;
+            pixel_row.push(pixel.mean());
         }
         pixels.push(pixel_row);
     }
-    output.put_bucket(&bucket, &pixels)?;
-
-    Ok(())
+    pixels
 }
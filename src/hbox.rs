@@ -8,14 +8,14 @@ use util::*;
 use aarect::*;
 
 #[derive(Debug)]
-pub struct HBox<'a> {
+pub struct HBox {
     pmin: Vec3<f64>,
     pmax: Vec3<f64>,
-    hlist: HitableList<'a>,
+    hlist: HitableList,
 }
 
-impl<'a> HBox<'a> {
-    pub fn new(p0: Vec3<f64>, p1: Vec3<f64>, material: Rc<Material>) -> HBox<'a> {
+impl HBox {
+    pub fn new(p0: Vec3<f64>, p1: Vec3<f64>, material: Arc<Material>) -> HBox {
         let mut hlist = HitableList::new();
         hlist.add_hitable(XYRect::new(p0.x, p1.x, p0.y, p1.y, p1.z, material.clone()));
         hlist.add_hitable(FlipNormals::new(Box::new(XYRect::new(p0.x, p1.x, p0.y, p1.y, p0.z, material.clone()))));
@@ -31,7 +31,7 @@ impl<'a> HBox<'a> {
     }
 }
 
-impl<'a> Hitable for HBox<'a> {
+impl Hitable for HBox {
     fn hit(&self, rng: &mut Rng, r: &Ray<f64>, t_min: f64, t_max: f64) -> Option<HitRecord> {
         self.hlist.hit(rng, r, t_min, t_max)
     }
@@ -39,4 +39,29 @@ impl<'a> Hitable for HBox<'a> {
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
         Some(AABB::new(self.pmin, self.pmax))
     }
+    fn pdf_value(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        // The faces are picked evenly.  The pdf of each face ignores the
+        // faces in front of it, which is what picking a face and then a
+        // point on it gives.
+        self.hlist.pdf_value(rng, o, v)
+    }
+    fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> {
+        self.hlist.random(rng, o)
+    }
+    fn is_emissive(&self) -> bool {
+        self.hlist.is_emissive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use texture::*;
+
+    #[test]
+    fn test_box_sampling() {
+        let m: Arc<Material> = Arc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(1., 1., 1.)))));
+        let b = HBox::new(Vec3::new(-1., 0.5, -1.), Vec3::new(1., 1.5, 0.5), m);
+        check_sampling(&b, &Vec3::new(0.5, 0., 0.3));
+    }
 }
@@ -3,6 +3,7 @@ use ray::Ray;
 use material::Material;
 use util::*;
 use aabb::*;
+use matrix4::Matrix4;
 
 /// A ray hit on a surface.
 #[derive(Debug, Clone, new)]
@@ -18,11 +19,11 @@ pub struct HitRecord {
     /// Normal vector from surface.
     pub normal: Vec3<f64>,
     /// Material of the surface where it hit.
-    pub material: Rc<Material>,
+    pub material: Arc<Material>,
 }
 
 /// Used for surfaces/objects that can be "hit" by a ray.
-pub trait Hitable: fmt::Debug {
+pub trait Hitable: fmt::Debug + Send + Sync {
     /// Test for hit against surface.
     /// Returns None if no hit.
     fn hit(&self, rng: &mut Rng, r: &Ray<f64>, t_min: f64, t_max: f64) -> Option<HitRecord>;
@@ -38,6 +39,37 @@ pub trait Hitable: fmt::Debug {
 
     fn pdf_value(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 { 0. }
     fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> { Vec3::new(1., 0., 0.) }
+
+    /// Whether any part of this object has an emissive material.
+    fn is_emissive(&self) -> bool { false }
+
+    /// For objects made up of other objects, add the parts that emit to
+    /// `lights` and return true, so that only those are sampled instead of
+    /// the whole object.
+    fn add_lights(&self, lights: &mut Vec<Arc<Hitable>>) -> bool { false }
+}
+
+/// Allows the same object to be shared, such as between the world and the
+/// list of lights.
+impl<T: Hitable + ?Sized> Hitable for Arc<T> {
+    fn hit(&self, rng: &mut Rng, r: &Ray<f64>, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        (**self).hit(rng, r, t_min, t_max)
+    }
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        (**self).bounding_box(t0, t1)
+    }
+    fn pdf_value(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        (**self).pdf_value(rng, o, v)
+    }
+    fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> {
+        (**self).random(rng, o)
+    }
+    fn is_emissive(&self) -> bool {
+        (**self).is_emissive()
+    }
+    fn add_lights(&self, lights: &mut Vec<Arc<Hitable>>) -> bool {
+        (**self).add_lights(lights)
+    }
 }
 
 #[derive(Debug, new)]
@@ -57,6 +89,15 @@ impl Hitable for FlipNormals {
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
         self.hitable.bounding_box(t0, t1)
     }
+    fn pdf_value(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        self.hitable.pdf_value(rng, o, v)
+    }
+    fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> {
+        self.hitable.random(rng, o)
+    }
+    fn is_emissive(&self) -> bool {
+        self.hitable.is_emissive()
+    }
 }
 
 #[derive(Debug, new)]
@@ -84,6 +125,16 @@ impl Hitable for Translate {
             None => None,
         }
     }
+    fn pdf_value(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        self.hitable.pdf_value(rng, &(*o - self.offset), v)
+    }
+    fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> {
+        // Directions are the same in both spaces.
+        self.hitable.random(rng, &(*o - self.offset))
+    }
+    fn is_emissive(&self) -> bool {
+        self.hitable.is_emissive()
+    }
 }
 
 #[derive(Debug)]
@@ -134,6 +185,20 @@ impl RotateY {
             bbox: bbox
         }
     }
+
+    /// Rotate from world space into object space.
+    fn to_object(&self, v: &Vec3<f64>) -> Vec3<f64> {
+        Vec3::new(self.cos_theta*v.x - self.sin_theta*v.z,
+                  v.y,
+                  self.sin_theta*v.x + self.cos_theta*v.z)
+    }
+
+    /// Rotate from object space into world space.
+    fn to_world(&self, v: &Vec3<f64>) -> Vec3<f64> {
+        Vec3::new(self.cos_theta*v.x + self.sin_theta*v.z,
+                  v.y,
+                  -self.sin_theta*v.x + self.cos_theta*v.z)
+    }
 }
 
 impl Hitable for RotateY {
@@ -161,6 +226,149 @@ impl Hitable for RotateY {
     fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
         self.bbox.clone()
     }
+    fn pdf_value(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        // Rotations don't change solid angles, so the pdf carries over.
+        self.hitable.pdf_value(rng, &self.to_object(o), &self.to_object(v))
+    }
+    fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> {
+        self.to_world(&self.hitable.random(rng, &self.to_object(o)))
+    }
+    fn is_emissive(&self) -> bool {
+        self.hitable.is_emissive()
+    }
 }
 
+/// An object placed in the world with an arbitrary affine transform.
+#[derive(Debug)]
+pub struct Transform {
+    hitable: Box<Hitable>,
+    /// Object space to world space.
+    matrix: Matrix4,
+    /// World space to object space.
+    inverse: Matrix4,
+    /// Transforms normals from object space to world space.
+    normal_matrix: Matrix4,
+}
 
+impl Transform {
+    /// `matrix` transforms from object space to world space.
+    ///
+    /// Panics if the matrix is not invertible.
+    pub fn new(hitable: Box<Hitable>, matrix: Matrix4) -> Transform {
+        let inverse = matrix.inverse().expect("Transform matrix is not invertible");
+        Transform {
+            hitable: hitable,
+            matrix: matrix,
+            inverse: inverse,
+            normal_matrix: inverse.transpose(),
+        }
+    }
+
+    pub fn matrix(&self) -> &Matrix4 {
+        &self.matrix
+    }
+}
+
+impl Hitable for Transform {
+    fn hit(&self, rng: &mut Rng, r: &Ray<f64>, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        // The direction is not normalized, so t is the same in both spaces.
+        let object_r = Ray::new_time(self.inverse.transform_point(&r.origin()),
+                                     self.inverse.transform_vector(&r.direction()),
+                                     r.time());
+        self.hitable.hit(rng, &object_r, t_min, t_max).map(|mut rec| {
+            rec.p = self.matrix.transform_point(&rec.p);
+            rec.normal = self.normal_matrix.transform_vector(&rec.normal).unit_vector();
+            rec
+        })
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.hitable.bounding_box(t0, t1).map(|bbox| {
+            let mut min = Vec3::new(f64::MAX, f64::MAX, f64::MAX);
+            let mut max = Vec3::new(-f64::MAX, -f64::MAX, -f64::MAX);
+            for i in 0..8 {
+                let corner = Vec3::new(if i & 1 == 0 { bbox.min().x } else { bbox.max().x },
+                                       if i & 2 == 0 { bbox.min().y } else { bbox.max().y },
+                                       if i & 4 == 0 { bbox.min().z } else { bbox.max().z });
+                let p = self.matrix.transform_point(&corner);
+                for c in 0..3 {
+                    min[c] = min[c].min(p[c]);
+                    max[c] = max[c].max(p[c]);
+                }
+            }
+            AABB::new(min, max)
+        })
+    }
+
+    fn pdf_value(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        let object_o = self.inverse.transform_point(o);
+        let object_v = self.inverse.transform_vector(v);
+        let pdf = self.hitable.pdf_value(rng, &object_o, &object_v);
+        if pdf == 0. {
+            return 0.;
+        }
+        // The pdf is per unit solid angle, and the transform stretches solid
+        // angles.  For a linear map A, the density of directions changes by
+        // |det A| / |A w|^3 (w a unit vector).
+        let a_w = self.inverse.transform_vector(&v.unit_vector()).length();
+        pdf * self.inverse.determinant3().abs() / (a_w * a_w * a_w)
+    }
+
+    fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> {
+        let object_o = self.inverse.transform_point(o);
+        // The sampled direction is (point - o), so transforming it as a
+        // vector gives the direction to the transformed point.
+        self.matrix.transform_vector(&self.hitable.random(rng, &object_o))
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.hitable.is_emissive()
+    }
+}
+
+/// Check that sampling `h` from `o` is consistent: the pdf integrates to one
+/// over the sphere of directions, and sampled directions hit the object.
+#[cfg(test)]
+pub fn check_sampling(h: &Hitable, o: &Vec3<f64>) {
+    use pdf::random_on_unit_sphere;
+    let mut rng = Rng::from_seed(0);
+    let n = 400000;
+    let mut total = 0.;
+    for _ in 0..n {
+        let v = random_on_unit_sphere(&mut rng);
+        total += h.pdf_value(&mut rng, o, &v) * 4. * PI;
+    }
+    let mean = total / n as f64;
+    assert!((mean - 1.).abs() < 0.03, "{:?}: pdf integrates to {}", h, mean);
+    for _ in 0..1000 {
+        let v = h.random(&mut rng, o);
+        assert!(h.pdf_value(&mut rng, o, &v) > 0., "{:?}: sampled {:?} has no pdf", h, v);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aarect::*;
+    use sphere::*;
+    use material::*;
+    use texture::*;
+
+    #[test]
+    fn test_wrapper_sampling() {
+        let m: Arc<Material> = Arc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(1., 1., 1.)))));
+        let rect = || Box::new(XZRect::new(-1., 2., -1., 1.5, 1., m.clone()));
+        let o = Vec3::new(0.5, 0.2, 0.3);
+        check_sampling(&FlipNormals::new(rect()), &o);
+        check_sampling(&Translate::new(rect(), Vec3::new(0.3, -0.5, 0.2)), &o);
+        check_sampling(&RotateY::new(rect(), 30.), &o);
+        // Rotated off the axis, so the rect is no longer level.
+        let tilted = Transform::new(rect(), Matrix4::rotation_z(20.));
+        check_sampling(&RotateY::new(Box::new(tilted), 45.), &o);
+        // Stretched unevenly, which changes solid angles differently in
+        // each direction.
+        check_sampling(&Transform::new(rect(), Matrix4::scale(&Vec3::new(1.5, 0.5, 0.7))), &o);
+        let sphere = Box::new(Sphere::new(Vec3::new(0., 1., 0.), 0.5, m.clone()));
+        check_sampling(&Transform::new(sphere, Matrix4::scale(&Vec3::new(2., 1., 0.5))), &o);
+    }
+}
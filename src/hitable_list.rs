@@ -3,23 +3,38 @@ use ray::Ray;
 use aabb::*;
 use util::*;
 use vec3::*;
+use bvh::{TraversalCounts, counting_traversal};
 
+/// Objects are shared (rather than boxed) so that the ones that emit can
+/// also go in the scene's list of lights (see `add_lights`).
 #[derive(Debug)]
-pub struct HitableList<'a> {
-    list: Vec<Box<Hitable + 'a>>,
+pub struct HitableList {
+    list: Vec<Arc<Hitable>>,
 }
 
 
-impl<'a> HitableList<'a> {
-    pub fn new() -> HitableList<'a> {
+impl HitableList {
+    pub fn new() -> HitableList {
         HitableList { list: Vec::new() }
     }
-    pub fn add_hitable<T: Hitable + 'a>(&mut self, h: T) {
-        self.list.push(Box::new(h));
+    pub fn add_hitable<T: Hitable + 'static>(&mut self, h: T) {
+        self.list.push(Arc::new(h));
+    }
+    pub fn add_boxed(&mut self, h: Box<Hitable>) {
+        self.list.push(Arc::from(h));
+    }
+    pub fn add_shared(&mut self, h: Arc<Hitable>) {
+        self.list.push(h);
+    }
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
     }
 }
 
-impl<'a> Hitable for HitableList<'a> {
+impl Hitable for HitableList {
     fn hit(&self, rng: &mut Rng, r: &Ray<f64>, t_min: f64, t_max: f64) -> Option<HitRecord> {
         let mut result: Option<HitRecord> = None;
         let mut closest_so_far = t_max;
@@ -29,6 +44,9 @@ impl<'a> Hitable for HitableList<'a> {
                 result = Some(hr);
             }
         }
+        if counting_traversal() {
+            TraversalCounts { nodes: 0, primitives: self.list.len() as u64 }.record();
+        }
         return result;
     }
 
@@ -67,5 +85,84 @@ impl<'a> Hitable for HitableList<'a> {
         return self.list[index].random(rng, o);
     }
 
+    fn is_emissive(&self) -> bool {
+        self.list.iter().any(|h| h.is_emissive())
+    }
+
+    fn add_lights(&self, lights: &mut Vec<Arc<Hitable>>) -> bool {
+        for h in &self.list {
+            if h.is_emissive() && !h.add_lights(lights) {
+                lights.push(h.clone());
+            }
+        }
+        true
+    }
+
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sphere::*;
+    use aarect::*;
+    use material::*;
+    use texture::*;
+
+    #[test]
+    fn test_sampling() {
+        let m: Arc<Material> = Arc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(1., 1., 1.)))));
+        let mut list = HitableList::new();
+        list.add_hitable(Sphere::new(Vec3::new(-1., 1., 0.), 0.5, m.clone()));
+        list.add_hitable(XZRect::new(0., 2., -1., 1., 2., m.clone()));
+        list.add_hitable(YZRect::new(-1., 1., -1., 1., 3., m));
+        check_sampling(&list, &Vec3::new(0.5, 0.5, 0.5));
+    }
+
+    #[test]
+    fn test_scene_lights() {
+        use bvh::*;
+        use scene::*;
+        use camera::*;
+        use output::*;
+        use ray::Ray;
+
+        // Only the light inside the nested lists and BVH is sampled, not the
+        // objects around it.
+        let mut rng = Rng::from_seed(0);
+        let light: Arc<Material> = Arc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(4., 4., 4.)))));
+        let grey: Arc<Material> = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.5, 0.5, 0.5)))));
+        let mut inner = HitableList::new();
+        inner.add_hitable(Sphere::new(Vec3::new(3., 3., 0.), 0.5, grey.clone()));
+        inner.add_hitable(XZRect::new(-1., 1., -1., 1., 3., light.clone()));
+        let mut objects: Vec<Box<Hitable>> = (0..20).map(|i| {
+            Box::new(Sphere::new(Vec3::new(i as f64 - 10., -1., 0.), 0.4, grey.clone())) as Box<Hitable>
+        }).collect();
+        objects.push(Box::new(inner));
+        let mut list = HitableList::new();
+        list.add_hitable(Sphere::new(Vec3::new(0., -1000., 0.), 998., grey.clone()));
+        list.add_hitable(BVH::new(objects, 0., 1.));
+        assert!(list.is_emissive());
+
+        let mut builder = SceneBuilder::new();
+        builder.add_hitable(list);
+        let camera = Camera::new(Vec3::new(0., 0., 5.), Vec3::zero(), Vec3::new(0., 1., 0.),
+                                 20., 1., 0., 5., 0., 1.);
+        let output = OutputSettings {
+            format: OutputFormat::Png,
+            filename_template: String::from("test.png"),
+            width: 1,
+            height: 1,
+            display: DisplaySettings::default(),
+            template_values: TemplateValues::default(),
+        };
+        let scene = builder.build(camera, 1, output);
+        assert_eq!(scene.light_shapes.len(), 1);
+        let o = Vec3::new(0.5, 0.5, 0.5);
+        for _ in 0..1000 {
+            let v = scene.light_shapes.random(&mut rng, &o);
+            assert!(scene.light_shapes.pdf_value(&mut rng, &o, &v) > 0.);
+            let rec = scene.world.hit(&mut rng, &Ray::new(o, v), 0.001, f64::MAX).unwrap();
+            assert!(rec.material.is_emissive());
+        }
+    }
+}
@@ -0,0 +1,505 @@
+/*
+Integrators work out how much light comes back along a camera ray.
+
+`PathTracer` is the normal renderer.  The others are mostly for looking at
+a scene while setting it up: `NormalView` shows the surface normals, and
+`AmbientOcclusion` shows how much of the sky each point can see.  `Heatmap`
+shows how much work it takes to find what each camera ray hits.
+*/
+
+use vec3::*;
+use ray::*;
+use scene::*;
+use util::*;
+use pdf::*;
+use hitable::*;
+use bvh::*;
+use std::str::FromStr;
+
+pub trait Integrator: fmt::Debug + Send + Sync {
+    /// Light arriving at the origin of `r` from its direction.
+    fn radiance(&self, rng: &mut Rng, r: &Ray<f64>, scene: &Scene) -> Vec3<f64>;
+
+    /// Whether `render` should count the tests done while looking for hits
+    /// (see `set_count_traversal`).
+    fn counts_traversal(&self) -> bool { false }
+}
+
+/// The integrators that don't need any settings, by name (for the command
+/// line).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum IntegratorKind {
+    Path,
+    Normals,
+    AmbientOcclusion,
+    Heatmap,
+}
+
+impl IntegratorKind {
+    pub fn integrator(&self) -> Box<Integrator> {
+        match *self {
+            IntegratorKind::Path => Box::new(PathTracer::new(Heuristic::Power)),
+            IntegratorKind::Normals => Box::new(NormalView::new()),
+            IntegratorKind::AmbientOcclusion => Box::new(AmbientOcclusion::new(f64::MAX)),
+            IntegratorKind::Heatmap => Box::new(Heatmap::new(DEFAULT_HEATMAP_SCALE)),
+        }
+    }
+}
+
+impl FromStr for IntegratorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<IntegratorKind, String> {
+        match s {
+            "path" => Ok(IntegratorKind::Path),
+            "normals" => Ok(IntegratorKind::Normals),
+            "ao" => Ok(IntegratorKind::AmbientOcclusion),
+            "heatmap" => Ok(IntegratorKind::Heatmap),
+            _ => Err(format!("unknown integrator `{}`", s)),
+        }
+    }
+}
+
+/// The kinds of bounce that have separate limits (see `BounceLimits`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Bounce {
+    Diffuse,
+    Specular,
+    Transmission,
+}
+
+/// Where a path is up to, for deciding when to stop it.
+#[derive(Debug, Clone, Copy)]
+struct PathState {
+    /// Number of bounces of each kind so far.
+    diffuse: u32,
+    specular: u32,
+    transmission: u32,
+    /// Product of the weights of all bounces so far (how much of the light
+    /// found further along the path reaches the camera).
+    throughput: Vec3<f64>,
+}
+
+impl PathState {
+    fn new() -> PathState {
+        PathState {
+            diffuse: 0,
+            specular: 0,
+            transmission: 0,
+            throughput: Vec3::new(1., 1., 1.),
+        }
+    }
+
+    fn depth(&self) -> u32 {
+        self.diffuse + self.specular + self.transmission
+    }
+
+    /// Continue the path with a bounce of the given kind, where `weight` is
+    /// the factor the light coming back along the new ray is multiplied
+    /// by.
+    ///
+    /// Returns None if the path should stop here.  Paths that survive
+    /// Russian roulette have their throughput scaled up to make up for the
+    /// ones that don't.
+    fn bounce(&self, rng: &mut Rng, settings: &RenderSettings, kind: Bounce, weight: &Vec3<f64>)
+              -> Option<PathState> {
+        let mut next = *self;
+        let (count, limit) = match kind {
+            Bounce::Diffuse => (&mut next.diffuse, settings.max_bounces.diffuse),
+            Bounce::Specular => (&mut next.specular, settings.max_bounces.specular),
+            Bounce::Transmission => (&mut next.transmission, settings.max_bounces.transmission),
+        };
+        if *count >= limit {
+            return None;
+        }
+        *count += 1;
+        next.throughput = next.throughput * *weight;
+        if self.depth() >= settings.roulette_depth {
+            let t = next.throughput;
+            let survive = t.x.max(t.y).max(t.z).min(1.);
+            if !(survive > 0.) || rng.rand64() >= survive {
+                return None;
+            }
+            next.throughput = next.throughput / survive;
+        }
+        Some(next)
+    }
+}
+
+/// How the light found by sampling the lights is weighed against the light
+/// found by following the bounced ray (multiple importance sampling).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Heuristic {
+    /// Weights in proportion to the pdfs.
+    Balance,
+    /// Weights in proportion to the squared pdfs, which favours whichever
+    /// strategy is better at finding a light even more.  Usually less noisy.
+    Power,
+}
+
+impl Heuristic {
+    /// Weight for a sample taken with pdf `a`, when the other strategy would
+    /// have picked it with pdf `b`.
+    fn weight(&self, a: f64, b: f64) -> f64 {
+        let (a, b) = match *self {
+            Heuristic::Balance => (a, b),
+            Heuristic::Power => (a * a, b * b),
+        };
+        if a + b > 0. { a / (a + b) } else { 0. }
+    }
+}
+
+impl FromStr for Heuristic {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Heuristic, String> {
+        match s {
+            "balance" => Ok(Heuristic::Balance),
+            "power" => Ok(Heuristic::Power),
+            _ => Err(format!("unknown heuristic `{}`", s)),
+        }
+    }
+}
+
+/// Path tracing with next event estimation: at every diffuse bounce a ray
+/// is sent towards the lights (and the background, if it is sampled), as
+/// well as the ray that continues the path.  A light can be found either
+/// way, so the two are combined with multiple importance sampling.
+///
+/// The continuing ray still aims at the lights half the time, so that
+/// objects sampled as lights without emitting (such as glass spheres)
+/// still help with the light they focus.
+#[derive(Debug, new)]
+pub struct PathTracer {
+    heuristic: Heuristic,
+}
+
+impl PathTracer {
+    /// Light arriving at `hrec` from one sample of `lights`, reflected
+    /// towards the origin of `r_in`.  `path_pdf` is the pdf the continuing
+    /// ray is picked with.
+    fn sample_lights(&self,
+                     rng: &mut Rng,
+                     scene: &Scene,
+                     r_in: &Ray<f64>,
+                     hrec: &HitRecord,
+                     attenuation: &Vec3<f64>,
+                     lights: &Pdf,
+                     path_pdf: &Pdf)
+                     -> Vec3<f64> {
+        let shadow_ray = Ray::new_time(hrec.p, lights.generate(rng), r_in.time());
+        let direction = shadow_ray.direction();
+        let light_pdf = lights.value(rng, &direction);
+        let scattering_pdf = hrec.material.scattering_pdf(r_in, hrec, &shadow_ray);
+        if !(light_pdf > 0.) || !(scattering_pdf > 0.) {
+            return Vec3::zero();
+        }
+        let light = match scene.world.hit(rng, &shadow_ray, 0.0001, f64::MAX) {
+            Some(lrec) => lrec.material.emitted(&shadow_ray, &lrec, lrec.u, lrec.v, &lrec.p),
+            None => scene.background.value(&direction),
+        };
+        let weight = self.heuristic.weight(light_pdf, path_pdf.value(rng, &direction));
+        attenuation * scattering_pdf * light * weight / light_pdf
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radiance(&self, rng: &mut Rng, r: &Ray<f64>, scene: &Scene) -> Vec3<f64> {
+        let settings = &scene.render_settings;
+        let mut ray = Ray::new_time(r.origin(), r.direction(), r.time());
+        let mut path = PathState::new();
+        let mut total = Vec3::zero();
+        // Weight for light the ray finds directly (less than 1 when the
+        // light could also have been found by sampling the lights).
+        let mut emission_weight = 1.;
+        loop {
+            // Use 0.0001 to ignore hits very near zero (the ray should travel
+            // at least some distance).
+            let hrec = match scene.world.hit(rng, &ray, 0.0001, f64::MAX) {
+                Some(hrec) => hrec,
+                None => {
+                    total += path.throughput * scene.background.value(&ray.direction()) * emission_weight;
+                    break;
+                },
+            };
+            let emitted = hrec.material.emitted(&ray, &hrec, hrec.u, hrec.v, &hrec.p);
+            total += path.throughput * emitted * emission_weight;
+            let srec = match hrec.material.scatter(rng, &ray, &hrec) {
+                Some(srec) => srec,
+                None => break,
+            };
+            let (kind, scattered, weight) = match srec.specular_ray {
+                Some(specular_ray) => {
+                    // Specular bounces can't sample the lights, so anything
+                    // they hit counts in full.
+                    emission_weight = 1.;
+                    let kind = if srec.transmission { Bounce::Transmission } else { Bounce::Specular };
+                    (kind, specular_ray, srec.attenuation)
+                },
+                None => {
+                    let plight = HitablePdf::new(hrec.p, &scene.light_shapes);
+                    let pbackground = BackgroundPdf::new(&*scene.background);
+                    let pboth = MixturePdf::new(&plight, &pbackground);
+                    let lights: Option<&Pdf> = match (scene.light_shapes.is_empty(),
+                                                      scene.background.is_sampled()) {
+                        (false, false) => Some(&plight),
+                        (true, true) => Some(&pbackground),
+                        (false, true) => Some(&pboth),
+                        (true, false) => None,
+                    };
+                    let spdf = srec.pdf.unwrap();
+                    let mixture;
+                    // Without any lights, only the material can be sampled.
+                    let p: &Pdf = match lights {
+                        Some(lights) => {
+                            mixture = MixturePdf::new(lights, &*spdf);
+                            &mixture
+                        },
+                        None => &*spdf,
+                    };
+                    if let Some(lights) = lights {
+                        // Only if the path could take another diffuse
+                        // bounce, so the limit means the same as without
+                        // sampling the lights.
+                        if path.diffuse < settings.max_bounces.diffuse {
+                            total += path.throughput *
+                                self.sample_lights(rng, scene, &ray, &hrec, &srec.attenuation, lights, p);
+                        }
+                    }
+                    let scattered = Ray::new_time(hrec.p, p.generate(rng), ray.time());
+                    let pdf_val = p.value(rng, &scattered.direction());
+                    emission_weight = match lights {
+                        Some(lights) => self.heuristic.weight(pdf_val, lights.value(rng, &scattered.direction())),
+                        None => 1.,
+                    };
+                    let weight = srec.attenuation*hrec.material.scattering_pdf(&ray, &hrec, &scattered) / pdf_val;
+                    (Bounce::Diffuse, scattered, weight)
+                },
+            };
+            path = match path.bounce(rng, settings, kind, &weight) {
+                Some(next) => next,
+                None => break,
+            };
+            ray = scattered;
+        }
+        total
+    }
+}
+
+/// Shows the surface normal where the ray hits, with each axis mapped from
+/// -1..1 to 0..1.  Misses are black.
+#[derive(Debug, new)]
+pub struct NormalView {}
+
+impl Integrator for NormalView {
+    fn radiance(&self, rng: &mut Rng, r: &Ray<f64>, scene: &Scene) -> Vec3<f64> {
+        match scene.world.hit(rng, r, 0.0001, f64::MAX) {
+            Some(hrec) => 0.5 * (hrec.normal.unit_vector() + Vec3::new(1., 1., 1.)),
+            None => Vec3::zero(),
+        }
+    }
+}
+
+/// Ambient occlusion: white where the surface can see in every direction
+/// above it, darker where other objects closer than `distance` are in the
+/// way.  Misses are white.
+#[derive(Debug, new)]
+pub struct AmbientOcclusion {
+    distance: f64,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance(&self, rng: &mut Rng, r: &Ray<f64>, scene: &Scene) -> Vec3<f64> {
+        let hrec = match scene.world.hit(rng, r, 0.0001, f64::MAX) {
+            Some(hrec) => hrec,
+            None => return Vec3::new(1., 1., 1.),
+        };
+        // Face the normal towards the ray, so both sides of thin surfaces
+        // work.
+        let normal = if dot(&hrec.normal, &r.direction()) > 0. { -hrec.normal } else { hrec.normal };
+        let direction = CosinePdf::new(&normal).generate(rng);
+        let occlusion_ray = Ray::new_time(hrec.p, direction.unit_vector(), r.time());
+        match scene.world.hit(rng, &occlusion_ray, 0.0001, self.distance) {
+            Some(_) => Vec3::zero(),
+            None => Vec3::new(1., 1., 1.),
+        }
+    }
+}
+
+/// Number of tests shown as the hottest color if the scale isn't given.
+pub const DEFAULT_HEATMAP_SCALE: f64 = 100.;
+
+/// Colors each pixel by how many BVH nodes and objects its camera ray was
+/// tested against, from blue (none) through green and yellow to red
+/// (`scale` tests or more).  Only the first hit is looked for, so lighting
+/// and materials make no difference.
+///
+/// The colors are written out like any other render: 8-bit outputs still
+/// apply the exposure, tone map and sRGB encoding.  With the default
+/// exposure and the "clamp" tone map, the ramp's five colors come out as
+/// they are, and the blends between them a little brighter.
+#[derive(Debug, new)]
+pub struct Heatmap {
+    scale: f64,
+}
+
+impl Heatmap {
+
+    fn color(t: f64) -> Vec3<f64> {
+        const RAMP: [[f64; 3]; 5] = [
+            [0., 0., 1.],
+            [0., 1., 1.],
+            [0., 1., 0.],
+            [1., 1., 0.],
+            [1., 0., 0.],
+        ];
+        let x = t.max(0.).min(1.) * (RAMP.len() - 1) as f64;
+        let i = (x as usize).min(RAMP.len() - 2);
+        let f = x - i as f64;
+        let (a, b) = (RAMP[i], RAMP[i + 1]);
+        Vec3::new(a[0] + f * (b[0] - a[0]),
+                  a[1] + f * (b[1] - a[1]),
+                  a[2] + f * (b[2] - a[2]))
+    }
+}
+
+impl Integrator for Heatmap {
+    fn radiance(&self, rng: &mut Rng, r: &Ray<f64>, scene: &Scene) -> Vec3<f64> {
+        take_traversal_counts();
+        scene.world.hit(rng, r, 0.0001, f64::MAX);
+        let counts = take_traversal_counts();
+        Heatmap::color((counts.nodes + counts.primitives) as f64 / self.scale)
+    }
+
+    fn counts_traversal(&self) -> bool {
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sphere::*;
+    use material::*;
+    use texture::*;
+    use camera::*;
+    use background::*;
+    use output::*;
+
+    #[test]
+    fn test_path_state() {
+        let mut settings = RenderSettings::default();
+        settings.max_bounces.specular = 2;
+        settings.roulette_depth = 1;
+        let mut rng = Rng::from_seed(0);
+        let white = Vec3::new(1., 1., 1.);
+
+        // Bright paths are never stopped by roulette, only by the limits.
+        let path = PathState::new();
+        let path = path.bounce(&mut rng, &settings, Bounce::Specular, &white).unwrap();
+        assert_eq!(path.throughput, white);
+        let path = path.bounce(&mut rng, &settings, Bounce::Specular, &white).unwrap();
+        assert!(path.bounce(&mut rng, &settings, Bounce::Specular, &white).is_none());
+        assert!(path.bounce(&mut rng, &settings, Bounce::Transmission, &white).is_some());
+
+        // Dim paths are stopped at random, but on average the light they
+        // carry is the same.
+        let dim = Vec3::new(0.2, 0.1, 0.);
+        let n = 100000;
+        let mut total = 0.;
+        let mut survived = 0;
+        for _ in 0..n {
+            if let Some(next) = path.bounce(&mut rng, &settings, Bounce::Diffuse, &dim) {
+                total += next.throughput.x;
+                survived += 1;
+            }
+        }
+        assert!((total / n as f64 - 0.2).abs() < 0.004, "{}", total / n as f64);
+        assert!(survived < n / 4, "{}", survived);
+
+        assert_eq!("ao".parse(), Ok(IntegratorKind::AmbientOcclusion));
+        assert_eq!("heatmap".parse(), Ok(IntegratorKind::Heatmap));
+        assert!("whitted".parse::<IntegratorKind>().is_err());
+    }
+
+    #[test]
+    fn test_path_tracer() {
+        assert_eq!(Heuristic::Balance.weight(1., 3.), 0.25);
+        assert_eq!(Heuristic::Power.weight(1., 3.), 0.1);
+        assert_eq!(Heuristic::Power.weight(0., 0.), 0.);
+
+        // A grey sphere lit by an even white environment (which is sampled
+        // like a light) reflects exactly its albedo, with both heuristics.
+        let mut builder = SceneBuilder::new();
+        let grey = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.5, 0.5, 0.5)))));
+        builder.add_hitable(Sphere::new(Vec3::zero(), 1., grey));
+        builder.set_background(Box::new(EnvironmentMap::new(4, 2, vec![Vec3::new(1., 1., 1.); 8], 0.)));
+        let camera = Camera::new(Vec3::new(0., 0., 5.), Vec3::zero(), Vec3::new(0., 1., 0.),
+                                 20., 1., 0., 5., 0., 1.);
+        let output = OutputSettings {
+            format: OutputFormat::Png,
+            filename_template: String::from("test.png"),
+            width: 1,
+            height: 1,
+            display: DisplaySettings::default(),
+            template_values: TemplateValues::default(),
+        };
+        let scene = builder.build(camera, 1, output);
+        let mut rng = Rng::from_seed(0);
+        let r = Ray::new(Vec3::new(0., 0., 5.), Vec3::new(0., 0.1, -1.));
+        for heuristic in &[Heuristic::Balance, Heuristic::Power] {
+            let tracer = PathTracer::new(*heuristic);
+            let n = 20000;
+            let mut total = 0.;
+            for _ in 0..n {
+                total += tracer.radiance(&mut rng, &r, &scene).x;
+            }
+            assert!((total / n as f64 - 0.5).abs() < 0.01, "{:?} {}", heuristic, total / n as f64);
+        }
+    }
+
+    #[test]
+    fn test_heatmap() {
+        assert_eq!(Heatmap::color(0.), Vec3::new(0., 0., 1.));
+        assert_eq!(Heatmap::color(0.5), Vec3::new(0., 1., 0.));
+        assert_eq!(Heatmap::color(1.), Vec3::new(1., 0., 0.));
+        assert_eq!(Heatmap::color(7.), Vec3::new(1., 0., 0.));
+
+        // Rays that go into the BVH do more tests.
+        let mut rng = Rng::from_seed(0);
+        let grey: Arc<Material> = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.5, 0.5, 0.5)))));
+        let spheres: Vec<Box<Hitable>> = (0..10).map(|i| {
+            Box::new(Sphere::new(Vec3::new(i as f64, 0., 0.), 0.4, grey.clone())) as Box<Hitable>
+        }).collect();
+        let mut builder = SceneBuilder::new();
+        builder.add_hitable(BVH::new(spheres, 0., 1.));
+        let camera = || Camera::new(Vec3::new(0., 0., 5.), Vec3::zero(), Vec3::new(0., 1., 0.),
+                                    20., 1., 0., 5., 0., 1.);
+        let output = OutputSettings {
+            format: OutputFormat::Png,
+            filename_template: String::from("test.png"),
+            width: 1,
+            height: 1,
+            display: DisplaySettings::default(),
+            template_values: TemplateValues::default(),
+        };
+        let scene = builder.build(camera(), 1, output.clone());
+        let heatmap = Heatmap::new(4.);
+        assert!(heatmap.counts_traversal());
+        set_count_traversal(true);
+        // A ray that misses the root box only tests that, and the BVH in
+        // the world list.
+        let miss = Ray::new(Vec3::new(0., 0., 5.), Vec3::new(0., 1., 0.));
+        assert_eq!(heatmap.radiance(&mut rng, &miss, &scene), Heatmap::color(0.5));
+        let hit = Ray::new(Vec3::new(3., 0., 5.), Vec3::new(0., 0., -1.));
+        assert_eq!(heatmap.radiance(&mut rng, &hit, &scene), Vec3::new(1., 0., 0.));
+
+        // Without a BVH, every object in the world is tested.
+        let mut builder = SceneBuilder::new();
+        for i in 0..3 {
+            builder.add_hitable(Sphere::new(Vec3::new(i as f64, 0., 0.), 0.4, grey.clone()));
+        }
+        let scene = builder.build(camera(), 1, output);
+        assert_eq!(heatmap.radiance(&mut rng, &miss, &scene), Heatmap::color(0.75));
+    }
+}
@@ -7,9 +7,14 @@ extern crate image;
 extern crate derive_new;
 #[cfg(feature="gui")]
 extern crate minifb;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
 
 
 pub mod vec3;
+pub mod matrix4;
 pub mod ray;
 pub mod hitable;
 pub mod hitable_list;
@@ -24,14 +29,21 @@ pub mod texture;
 pub mod perlin;
 pub mod aarect;
 pub mod hbox;
-// pub mod constant_medium;
+pub mod triangle;
+pub mod obj;
+pub mod constant_medium;
 pub mod onb;
 pub mod pdf;
+pub mod background;
+pub mod sky;
+pub mod integrator;
 pub mod core;
 pub mod scene;
+pub mod scene_file;
 pub mod output;
 
 pub use vec3::*;
+pub use matrix4::*;
 pub use ray::*;
 pub use hitable::*;
 pub use hitable_list::*;
@@ -46,11 +58,17 @@ pub use texture::*;
 pub use perlin::*;
 pub use aarect::*;
 pub use hbox::*;
-// pub use constant_medium::*;
+pub use triangle::*;
+pub use obj::*;
+pub use constant_medium::*;
 pub use onb::*;
 pub use pdf::*;
+pub use background::*;
+pub use sky::*;
 pub use scene::*;
+pub use scene_file::*;
 pub use output::*;
+pub use integrator::*;
 pub use core::*;
 
 macro_rules! perrln {
@@ -15,7 +15,7 @@ fn two_spheres(rng: &mut Rng) -> Box<Hitable> {
         Box::new(ConstantTexture::new(Vec3::new(0.9, 0.9, 0.9)))
     );
     let mut list = HitableList::new();
-    let mat = Rc::new(Lambertian::new(Box::new(checker)));
+    let mat = Arc::new(Lambertian::new(Box::new(checker)));
     list.add_hitable(Sphere::new(Vec3::new(0., -10., 0.), 10., mat.clone()));
     list.add_hitable(Sphere::new(Vec3::new(0.,  10., 0.), 10., mat.clone()));
     return Box::new(list);
@@ -24,7 +24,7 @@ fn two_spheres(rng: &mut Rng) -> Box<Hitable> {
 fn two_perlin_spheres(rng: &mut Rng) -> Box<Hitable> {
     let pertext = NoiseTexture::new(4.);
     let mut list = HitableList::new();
-    let mat = Rc::new(Lambertian::new(Box::new(pertext)));
+    let mat = Arc::new(Lambertian::new(Box::new(pertext)));
     list.add_hitable(Sphere::new(Vec3::new(0., -1000., 0.), 1000., mat.clone()));
     list.add_hitable(Sphere::new(Vec3::new(0., 2., 0.), 2., mat.clone()));
     return Box::new(list);
@@ -33,14 +33,14 @@ fn two_perlin_spheres(rng: &mut Rng) -> Box<Hitable> {
 fn earth() -> Box<Hitable> {
     let img = image::open("earthmap1k.jpg").unwrap();
     let mat = Lambertian::new(Box::new(ImageTexture::new(img)));
-    return Box::new(Sphere::new(Vec3::zero(), 2., Rc::new(mat)));
+    return Box::new(Sphere::new(Vec3::zero(), 2., Arc::new(mat)));
 }
 
 fn simple_light() -> Box<Hitable> {
     let pertext = NoiseTexture::new(4.);
-    let lamb_mat = Rc::new(Lambertian::new(Box::new(pertext)));
+    let lamb_mat = Arc::new(Lambertian::new(Box::new(pertext)));
     let ctex = ConstantTexture::new(Vec3::new(4.,4.,4.));
-    let lit_mat = Rc::new(DiffuseLight::new(Box::new(ctex)));
+    let lit_mat = Arc::new(DiffuseLight::new(Box::new(ctex)));
     let mut list = HitableList::new();
     list.add_hitable(Sphere::new(Vec3::new(0., -1000., 0.), 1000., lamb_mat.clone()));
     list.add_hitable(Sphere::new(Vec3::new(0., 2., 0.), 2., lamb_mat.clone()));
@@ -51,11 +51,11 @@ fn simple_light() -> Box<Hitable> {
 */
 
 fn cornell_box() -> Scene {
-    let mut list = HitableList::new();
-    let red = Rc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.65, 0.05, 0.05)))));
-    let white = Rc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.73, 0.73, 0.73)))));
-    let green = Rc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.12, 0.45, 0.15)))));
-    let light = Rc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(15., 15., 15.)))));
+    let mut list = SceneBuilder::new();
+    let red = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.65, 0.05, 0.05)))));
+    let white = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.73, 0.73, 0.73)))));
+    let green = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.12, 0.45, 0.15)))));
+    let light = Arc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(15., 15., 15.)))));
     list.add_hitable(FlipNormals::new(Box::new(YZRect::new(0., 555., 0., 555., 555., green.clone()))));
     list.add_hitable(YZRect::new(0., 555., 0., 555., 0., red.clone()));
     list.add_hitable(FlipNormals::new(Box::new(XZRect::new(213., 343., 227., 332., 554., light.clone()))));
@@ -63,28 +63,22 @@ fn cornell_box() -> Scene {
     list.add_hitable(XZRect::new(0., 555., 0., 555., 0., white.clone()));
     list.add_hitable(FlipNormals::new(Box::new(XYRect::new(0., 555., 0., 555., 555., white.clone()))));
 
-    let glass = Rc::new(Dielectric::new(1.5));
-    list.add_hitable(Sphere::new(Vec3::new(190., 90., 190.), 90., glass.clone()));
+    // The glass sphere focuses light, so sample it like a light.
+    let glass = Arc::new(Dielectric::new(1.5));
+    list.add_sampled(Box::new(Sphere::new(Vec3::new(190., 90., 190.), 90., glass.clone())));
 
     // let b = Box::new(HBox::new(Vec3::new(0., 0., 0.), Vec3::new(165., 165., 165.), white.clone()));
     // list.add_hitable(Translate::new(Box::new(
     //     RotateY::new(b, -18.)), Vec3::new(130., 0., 65.)));
-    // let aluminum = Rc::new(Metal::new(Vec3::new(0.8, 0.85, 0.88), 0.));
+    // let aluminum = Arc::new(Metal::new(Vec3::new(0.8, 0.85, 0.88), 0.));
     let b = Box::new(HBox::new(Vec3::new(0., 0., 0.), Vec3::new(165., 330., 165.), white.clone()));
     list.add_hitable(Translate::new(Box::new(RotateY::new(b, 15.)), Vec3::new(265., 0., 295.)));
 
+    return cornell_scene("cornell_box", list);
+}
 
-    // XXX: This should be a copy or reference.
-    // Use a temporary material, since this is only used for finding the
-    // light, and I don't want to muck with making material Optional.
-    let red = Rc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.65, 0.05, 0.05)))));
-    let light_shape = XZRect::new(213., 343., 227., 332., 554., red.clone());
-    let glass_sphere = Sphere::new(Vec3::new(190., 90., 90.), 90., red.clone());
-    let mut light_shapes = HitableList::new();
-    light_shapes.add_hitable(light_shape);
-    light_shapes.add_hitable(glass_sphere);
-
-
+/// Wrap a Cornell box world up in a scene with the standard camera.
+fn cornell_scene(name: &str, world: SceneBuilder) -> Scene {
     let lookfrom = Vec3::new(278., 278., -800.);
     let lookat = Vec3::new(278., 278., 0.);
     let dist_to_focus = 10.0;//(lookfrom-lookat).length();
@@ -107,27 +101,27 @@ fn cornell_box() -> Scene {
         filename_template: String::from("output.ppm"),
         width: nx,
         height: ny,
+        display: DisplaySettings::default(),
+        template_values: TemplateValues {
+            scene: String::from(name),
+            ..TemplateValues::default()
+        },
     };
 
-    return Scene {
-        world: Box::new(list),
-        light_shapes: Box::new(light_shapes),
-        camera: camera,
-        output_settings: output,
-        num_samples: 100,
-    }
+    return world.build(camera, 100, output);
 }
 
-/*
-fn cornell_smoke() -> Box<Hitable> {
-    let mut list = HitableList::new();
-    let red = Rc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.65, 0.05, 0.05)))));
-    let white = Rc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.73, 0.73, 0.73)))));
-    let green = Rc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.12, 0.45, 0.15)))));
-    let light = Rc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(7., 7., 7.)))));
+fn cornell_smoke() -> Scene {
+    let mut list = SceneBuilder::new();
+    let red = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.65, 0.05, 0.05)))));
+    let white = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.73, 0.73, 0.73)))));
+    let green = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.12, 0.45, 0.15)))));
+    let light = Arc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(7., 7., 7.)))));
     list.add_hitable(FlipNormals::new(Box::new(YZRect::new(0., 555., 0., 555., 555., green.clone()))));
     list.add_hitable(YZRect::new(0., 555., 0., 555., 0., red.clone()));
-    list.add_hitable(XZRect::new(113., 443., 127., 432., 554., light.clone()));
+    // The light only emits on the side its normal faces, so flip it to face
+    // down into the box.
+    list.add_hitable(FlipNormals::new(Box::new(XZRect::new(113., 443., 127., 432., 554., light.clone()))));
     list.add_hitable(FlipNormals::new(Box::new(XZRect::new(0., 555., 0., 555., 555., white.clone()))));
     list.add_hitable(XZRect::new(0., 555., 0., 555., 0., white.clone()));
     list.add_hitable(FlipNormals::new(Box::new(XYRect::new(0., 555., 0., 555., 555., white.clone()))));
@@ -139,9 +133,9 @@ fn cornell_smoke() -> Box<Hitable> {
     let b = Translate::new(Box::new(RotateY::new(b, 15.)), Vec3::new(265., 0., 295.));
     let b = ConstantMedium::new(Box::new(b), 0.01, Box::new(ConstantTexture::new(Vec3::new(0., 0., 0.))));
     list.add_hitable(b);
-    return Box::new(list);
+    return cornell_scene("cornell_smoke", list);
 }
-*/
+
 /*
 fn random_scene(rng: &mut Rng) -> Box<Hitable> {
     let mut list: Vec<Box<Hitable>> = Vec::new();
@@ -151,7 +145,7 @@ fn random_scene(rng: &mut Rng) -> Box<Hitable> {
     );
     list.push(Box::new(Sphere::new(Vec3::new(0.0, -1000.0, 0.0),
                                    1000.0,
-                                   Rc::new(Lambertian::new(Box::new(checker))))));
+                                   Arc::new(Lambertian::new(Box::new(checker))))));
     // XXX: Not sure why explicit i8 is required here to cast to f64.
     for a in -10..10i8 {
         for b in -10..10i8 {
@@ -164,7 +158,7 @@ fn random_scene(rng: &mut Rng) -> Box<Hitable> {
                     // diffuse
                     let texture = ConstantTexture::new(Vec3::new(rng.rand64()*rng.rand64(), rng.rand64()*rng.rand64(), rng.rand64()*rng.rand64()));
                     list.push(Box::new(
-                        MovingSphere::new(center, center+Vec3::new(0.0,0.5*rng.rand64(), 0.0), 0.0, 1.0, 0.2, Rc::new(Lambertian::new(
+                        MovingSphere::new(center, center+Vec3::new(0.0,0.5*rng.rand64(), 0.0), 0.0, 1.0, 0.2, Arc::new(Lambertian::new(
                             Box::new(texture))))));
                 } else if choose_mat < 0.95 {
                     // metal
@@ -172,23 +166,23 @@ fn random_scene(rng: &mut Rng) -> Box<Hitable> {
                                                    0.5 * (1.0 + rng.rand64()),
                                                    0.5 * (1.0 + rng.rand64())),
                                          0.5 * rng.rand64());
-                    list.push(Box::new(Sphere::new(center, 0.2, Rc::new(mat))));
+                    list.push(Box::new(Sphere::new(center, 0.2, Arc::new(mat))));
                 } else {
                     // glass
-                    list.push(Box::new(Sphere::new(center, 0.2, Rc::new(Dielectric::new(1.5)))));
+                    list.push(Box::new(Sphere::new(center, 0.2, Arc::new(Dielectric::new(1.5)))));
                 }
             }
         }
     }
-    list.push(Box::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, Rc::new(Dielectric::new(1.5)))));
+    list.push(Box::new(Sphere::new(Vec3::new(0.0, 1.0, 0.0), 1.0, Arc::new(Dielectric::new(1.5)))));
     let texture = ConstantTexture::new(Vec3::new(0.4, 0.2, 0.1));
     list.push(Box::new(Sphere::new(Vec3::new(-4.0, 1.0, 0.0),
                                    1.0,
-                                   Rc::new(Lambertian::new(Box::new(texture))))));
+                                   Arc::new(Lambertian::new(Box::new(texture))))));
     list.push(Box::new(Sphere::new(Vec3::new(4.0, 1.0, 0.0),
                                    1.0,
-                                   Rc::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0)))));
-    let bvh = BVHNode::new(rng, list, 0.0, 1.0);
+                                   Arc::new(Metal::new(Vec3::new(0.7, 0.6, 0.5), 0.0)))));
+    let bvh = BVH::new(list, 0.0, 1.0);
     return Box::new(bvh);
 }
 */
@@ -200,8 +194,8 @@ fn final_scene() -> Box<Hitable> {
     let mut list = HitableList::new();
     let mut boxlist: Vec<Box<Hitable>> = Vec::new();
     let mut boxlist2: Vec<Box<Hitable>> = Vec::new();
-    let white = Rc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.73, 0.73, 0.73)))));
-    let ground = Rc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.48, 0.83, 0.53)))));
+    let white = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.73, 0.73, 0.73)))));
+    let ground = Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.48, 0.83, 0.53)))));
     for i in 0..num_boxes {
         for j in 0..num_boxes {
             let w = 100.;
@@ -215,27 +209,27 @@ fn final_scene() -> Box<Hitable> {
             boxlist.push(Box::new(b));
         }
     }
-    list.add_hitable(BVHNode::new(&mut rng, boxlist, 0., 1.));
-    let light = Rc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(7., 7., 7.)))));
+    list.add_hitable(BVH::new(boxlist, 0., 1.));
+    let light = Arc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(7., 7., 7.)))));
     list.add_hitable(XZRect::new(123., 432., 147., 412., 554., light.clone()));
     let center = Vec3::new(400., 400., 200.);
-    list.add_hitable(MovingSphere::new(center, center+Vec3::new(30., 0., 0.), 0., 1., 50., Rc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.7, 0.3, 0.1)))))));
-    list.add_hitable(Sphere::new(Vec3::new(260., 150., 45.), 50., Rc::new(Dielectric::new(1.5))));
-    list.add_hitable(Sphere::new(Vec3::new(0., 150., 145.), 50., Rc::new(Metal::new(Vec3::new(0.8, 0.8, 0.9), 10.))));
-    let boundary = Sphere::new(Vec3::new(360., 150., 145.), 70., Rc::new(Dielectric::new(1.5)));
+    list.add_hitable(MovingSphere::new(center, center+Vec3::new(30., 0., 0.), 0., 1., 50., Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.7, 0.3, 0.1)))))));
+    list.add_hitable(Sphere::new(Vec3::new(260., 150., 45.), 50., Arc::new(Dielectric::new(1.5))));
+    list.add_hitable(Sphere::new(Vec3::new(0., 150., 145.), 50., Arc::new(Metal::new(Vec3::new(0.8, 0.8, 0.9), 10.))));
+    let boundary = Sphere::new(Vec3::new(360., 150., 145.), 70., Arc::new(Dielectric::new(1.5)));
     let boundary2 = boundary.clone();
     list.add_hitable(boundary);
     list.add_hitable(ConstantMedium::new(Box::new(boundary2), 0.2, Box::new(ConstantTexture::new(Vec3::new(0.2, 0.4, 0.9)))));
-    let boundary = Sphere::new(Vec3::zero(), 5000., Rc::new(Dielectric::new(1.5)));
+    let boundary = Sphere::new(Vec3::zero(), 5000., Arc::new(Dielectric::new(1.5)));
     let img = image::open("earthmap1k.jpg").unwrap();
     let emat = Lambertian::new(Box::new(ImageTexture::new(img)));
-    list.add_hitable(Sphere::new(Vec3::new(400., 200., 400.), 100., Rc::new(emat)));
+    list.add_hitable(Sphere::new(Vec3::new(400., 200., 400.), 100., Arc::new(emat)));
     let pertext = NoiseTexture::new(0.1);
-    list.add_hitable(Sphere::new(Vec3::new(220., 280., 300.), 80., Rc::new(Lambertian::new(Box::new(pertext)))));
+    list.add_hitable(Sphere::new(Vec3::new(220., 280., 300.), 80., Arc::new(Lambertian::new(Box::new(pertext)))));
     for j in 0..1000 {
         boxlist2.push(Box::new(Sphere::new(Vec3::new(165.*rng.rand64(), 165.*rng.rand64(), 165.*rng.rand64()), 10., white.clone())));
     }
-    list.add_hitable(Translate::new(Box::new(RotateY::new(Box::new(BVHNode::new(&mut rng, boxlist2, 0., 1.)), 15.)), Vec3::new(-100., 270., 395.)));
+    list.add_hitable(Translate::new(Box::new(RotateY::new(Box::new(BVH::new(boxlist2, 0., 1.)), 15.)), Vec3::new(-100., 270., 395.)));
     return Box::new(list);
 }
 */
@@ -250,6 +244,33 @@ fn is_numeric(val: String) -> std::result::Result<(), String> {
     }
 }
 
+fn is_positive(val: String) -> std::result::Result<(), String> {
+    match val.parse::<f64>() {
+        Ok(v) if v.is_finite() && v > 0. => Ok(()),
+        _ => Err(String::from("Value must be a positive number.")),
+    }
+}
+
+/// Parse a frame range, either `START..END` (inclusive) or a single frame.
+fn parse_frames(val: &str) -> Option<(u32, u32)> {
+    let parts: Vec<&str> = val.split("..").collect();
+    match parts.len() {
+        1 => parts[0].parse().ok().map(|frame| (frame, frame)),
+        2 => match (parts[0].parse(), parts[1].parse()) {
+            (Ok(start), Ok(end)) if start <= end => Some((start, end)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+fn is_frame_range(val: String) -> std::result::Result<(), String> {
+    match parse_frames(&val) {
+        Some(..) => Ok(()),
+        None => Err(String::from("Value must be a frame number or a range like 1..48.")),
+    }
+}
+
 macro_rules! arg_value_with_default {
     ($m:ident, $v:expr, $t:ty, $d:expr) => {
         arg_value_with_default!($m.value_of($v), $t, $d)
@@ -277,7 +298,7 @@ fn main() {
             .value_name("OUTPUT")
             .display_order(1)
             .conflicts_with("gui")
-            .help("Output filename"))
+            .help("Output filename, which may contain tokens such as {scene}, {frame:04}, {pass} and {ext}"))
         .arg(clap::Arg::with_name("width")
             .long("width")
             .takes_value(true)
@@ -289,7 +310,124 @@ fn main() {
             .takes_value(true)
             .value_name("HEIGHT")
             .display_order(3)
-            .help("Image height"));
+            .help("Image height"))
+        .arg(clap::Arg::with_name("threads")
+            .long("threads")
+            .takes_value(true)
+            .value_name("THREADS")
+            .display_order(4)
+            .validator(is_numeric)
+            .help("Number of render threads (defaults to the number of CPUs)"))
+        .arg(clap::Arg::with_name("progressive")
+            .long("progressive")
+            .takes_value(true)
+            .value_name("SAMPLES")
+            .display_order(5)
+            .validator(is_numeric)
+            .help("Render in passes of SAMPLES samples per pixel, updating the output after each pass"))
+        .arg(clap::Arg::with_name("noise-threshold")
+            .long("noise-threshold")
+            .takes_value(true)
+            .value_name("NOISE")
+            .display_order(6)
+            .validator(is_positive)
+            .help("Enable adaptive sampling, sampling each pixel until its relative error is below NOISE"))
+        .arg(clap::Arg::with_name("min-samples")
+            .long("min-samples")
+            .takes_value(true)
+            .value_name("SAMPLES")
+            .display_order(7)
+            .requires("noise-threshold")
+            .validator(is_numeric)
+            .help("Minimum samples per pixel with adaptive sampling"))
+        .arg(clap::Arg::with_name("max-samples")
+            .long("max-samples")
+            .takes_value(true)
+            .value_name("SAMPLES")
+            .display_order(8)
+            .requires("noise-threshold")
+            .validator(is_numeric)
+            .help("Maximum samples per pixel with adaptive sampling"))
+        .arg(clap::Arg::with_name("seed")
+            .long("seed")
+            .takes_value(true)
+            .value_name("SEED")
+            .display_order(9)
+            .help("Seed for the random number generator (renders with the same seed are identical)"))
+        .arg(clap::Arg::with_name("scene")
+            .long("scene")
+            .takes_value(true)
+            .value_name("FILE")
+            .display_order(10)
+            .help("Scene description file to render (defaults to the built-in Cornell box)"))
+        .arg(clap::Arg::with_name("builtin")
+            .long("builtin")
+            .takes_value(true)
+            .value_name("NAME")
+            .possible_values(&["cornell_box", "cornell_smoke"])
+            .conflicts_with("scene")
+            .display_order(11)
+            .help("Built-in scene to render"))
+        .arg(clap::Arg::with_name("exposure")
+            .long("exposure")
+            .takes_value(true)
+            .value_name("STOPS")
+            .display_order(12)
+            .allow_hyphen_values(true)
+            .help("Exposure adjustment for 8-bit outputs, in stops"))
+        .arg(clap::Arg::with_name("tonemap")
+            .long("tonemap")
+            .takes_value(true)
+            .value_name("OPERATOR")
+            .possible_values(&["clamp", "reinhard", "aces"])
+            .display_order(13)
+            .help("Tone mapping operator for 8-bit outputs"))
+        .arg(clap::Arg::with_name("frames")
+            .long("frames")
+            .takes_value(true)
+            .value_name("START..END")
+            .display_order(14)
+            .validator(is_frame_range)
+            .help("Render a range of animation frames (the output filename needs a {frame} token)"))
+        .arg(clap::Arg::with_name("max-diffuse")
+            .long("max-diffuse")
+            .takes_value(true)
+            .value_name("BOUNCES")
+            .display_order(15)
+            .validator(is_numeric)
+            .help("Maximum number of diffuse bounces per path"))
+        .arg(clap::Arg::with_name("max-specular")
+            .long("max-specular")
+            .takes_value(true)
+            .value_name("BOUNCES")
+            .display_order(16)
+            .validator(is_numeric)
+            .help("Maximum number of specular (mirror) bounces per path"))
+        .arg(clap::Arg::with_name("max-transmission")
+            .long("max-transmission")
+            .takes_value(true)
+            .value_name("BOUNCES")
+            .display_order(17)
+            .validator(is_numeric)
+            .help("Maximum number of times a path may refract through glass"))
+        .arg(clap::Arg::with_name("roulette-depth")
+            .long("roulette-depth")
+            .takes_value(true)
+            .value_name("BOUNCES")
+            .display_order(18)
+            .validator(is_numeric)
+            .help("Number of bounces before Russian roulette may stop a path"))
+        .arg(clap::Arg::with_name("integrator")
+            .long("integrator")
+            .takes_value(true)
+            .value_name("NAME")
+            .possible_values(&["path", "normals", "ao", "heatmap"])
+            .display_order(19)
+            .help("How to render the image: path tracing, surface normals, ambient occlusion or intersection tests per pixel"))
+        .arg(clap::Arg::with_name("bvh-stats")
+            .long("bvh-stats")
+            .display_order(20)
+            .help("Print the size, depth and SAH cost of each BVH as it is built"));
     #[cfg(feature="gui")]
     {
         app = app.arg(clap::Arg::with_name("gui")
@@ -298,7 +436,23 @@ fn main() {
     }
     let matches = app.get_matches();
 
-    let mut scene = cornell_box();
+    let frames = matches.value_of("frames").map(|v| parse_frames(v).unwrap());
+    if matches.is_present("bvh-stats") {
+        set_report_stats(true);
+    }
+    let mut scene = match matches.value_of("scene") {
+        Some(path) => match load_scene_frames(path, frames) {
+            Ok(scene) => scene,
+            Err(e) => {
+                let desc = format!("Invalid scene file {}: {}", path, e);
+                clap::Error::with_description(&desc, clap::ErrorKind::ValueValidation).exit();
+            }
+        },
+        None => match matches.value_of("builtin") {
+            Some("cornell_smoke") => cornell_smoke(),
+            _ => cornell_box(),
+        },
+    };
     #[cfg(feature="gui")]
     {
         if matches.is_present("gui") {
@@ -308,7 +462,7 @@ fn main() {
     if matches.is_present("output") {
         let filename = matches.value_of("output").unwrap().to_string();
         if let Err(e) = scene.output_settings.set_filename_template(filename) {
-            let desc = format!("Invalid filename: {}", e.description());
+            let desc = format!("Invalid filename: {}", e);
             clap::Error::with_description(&desc, clap::ErrorKind::ValueValidation).exit();
             // perrln!();
             // std::process::exit(1);
@@ -316,13 +470,69 @@ fn main() {
     }
     scene.output_settings.width = arg_value_with_default!(matches, "width", u32, scene.output_settings.width);
     scene.output_settings.height = arg_value_with_default!(matches, "height", u32, scene.output_settings.height);
+    scene.output_settings.display.exposure = arg_value_with_default!(matches, "exposure", f64, scene.output_settings.display.exposure);
+    if let Some(tonemap) = matches.value_of("tonemap") {
+        scene.output_settings.display.tone_map = tonemap.parse().unwrap();
+    }
+    scene.render_settings.num_threads = arg_value_with_default!(matches, "threads", u32, scene.render_settings.num_threads);
+    if matches.is_present("progressive") {
+        scene.render_settings.progressive_samples = Some(arg_value_with_default!(matches, "progressive", u32, 1));
+    }
+    scene.render_settings.seed = arg_value_with_default!(matches, "seed", u64, scene.render_settings.seed);
+    if matches.is_present("noise-threshold") {
+        let max_samples = arg_value_with_default!(matches, "max-samples", u32, scene.num_samples);
+        scene.render_settings.adaptive = Some(AdaptiveSettings {
+            min_samples: arg_value_with_default!(matches, "min-samples", u32, std::cmp::min(16, max_samples)),
+            max_samples: max_samples,
+            noise_threshold: arg_value_with_default!(matches, "noise-threshold", f64, 0.),
+        });
+    }
 
-    let mut output = new_output(&scene.output_settings, &scene).unwrap();
-    render(&scene, &scene.output_settings, &mut output);
-    #[cfg(feature="gui")]
     {
-        if matches.is_present("gui") {
-            output.wait_to_exit();
+        let bounces = &mut scene.render_settings.max_bounces;
+        bounces.diffuse = arg_value_with_default!(matches, "max-diffuse", u32, bounces.diffuse);
+        bounces.specular = arg_value_with_default!(matches, "max-specular", u32, bounces.specular);
+        bounces.transmission = arg_value_with_default!(matches, "max-transmission", u32, bounces.transmission);
+    }
+    scene.render_settings.roulette_depth = arg_value_with_default!(matches, "roulette-depth", u32, scene.render_settings.roulette_depth);
+    if let Some(name) = matches.value_of("integrator") {
+        let kind: IntegratorKind = name.parse().unwrap();
+        scene.integrator = kind.integrator();
+    }
+
+    if let Some(frames) = frames {
+        scene.render_settings.frames = frames;
+    }
+    let (first_frame, last_frame) = scene.render_settings.frames;
+    if last_frame > first_frame && !scene.output_settings.filename_template.contains("{frame") {
+        clap::Error::with_description("Rendering more than one frame needs a {frame} token in the output filename",
+                                      clap::ErrorKind::ValueValidation).exit();
+    }
+
+    // One output (and window) is used for every frame.
+    scene.set_frame(first_frame);
+    let mut output = match new_output(&scene.output_settings, &scene) {
+        Ok(output) => output,
+        Err(e) => {
+            eprintln!("Could not create output: {}", e);
+            std::process::exit(1);
+        }
+    };
+    for frame in first_frame..last_frame+1 {
+        scene.set_frame(frame);
+        output.set_frame(frame);
+        if let Err(e) = render(&scene, &scene.output_settings, &mut output) {
+            if let Some(&Error::ExitRequested) = e.downcast_ref::<Error>() {
+                return;
+            }
+            eprintln!("Frame {} failed: {}", frame, e);
+            std::process::exit(1);
+        }
+        #[cfg(feature="gui")]
+        {
+            if matches.is_present("gui") && frame == last_frame {
+                output.wait_to_exit();
+            }
         }
     }
 }
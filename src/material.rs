@@ -41,12 +41,15 @@ fn schlick(cosine: f64, ref_idx: f64) -> f64 {
 #[derive(Debug, new)]
 pub struct ScatterRecord {
     pub specular_ray: Option<Ray<f64>>,
+    /// Whether `specular_ray` passes through the surface (refraction) rather
+    /// than reflecting off it.
+    pub transmission: bool,
     pub attenuation: Vec3<f64>,
     pub pdf: Option<Box<Pdf>>
 }
 
 
-pub trait Material: fmt::Debug {
+pub trait Material: fmt::Debug + Send + Sync {
     /// Return is (scattered, abledo, pdf) where scattered is the direction
     /// the ray should scatter in.  Albedo is the attenuation of the
     /// color.  pdf is f64.  Return None if there is no scatter.
@@ -67,6 +70,12 @@ pub trait Material: fmt::Debug {
     fn emitted(&self, r_in: &Ray<f64>, rec: &HitRecord, u: f64, v: f64, p: &Vec3<f64>) -> Vec3<f64> {
         Vec3::zero()
     }
+
+    /// Whether `emitted` can return anything.  Objects with an emissive
+    /// material are sampled as lights.
+    fn is_emissive(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Debug)]
@@ -88,6 +97,7 @@ impl Material for Lambertian {
                -> Option<(ScatterRecord)> {
         Some(ScatterRecord{
             specular_ray: None,
+            transmission: false,
             attenuation: self.albedo.value(rec.u, rec.v, &rec.p),
             pdf: Some(Box::new(CosinePdf::new(&rec.normal)))
         })
@@ -143,6 +153,7 @@ impl Material for Metal {
         let reflected = reflect(&r_in.direction().unit_vector(), &hrec.normal);
         Some(ScatterRecord{
             specular_ray: Some(Ray::new(hrec.p, reflected + self.fuzz*random_in_unit_sphere(rng))),
+            transmission: false,
             attenuation: self.albedo,
             pdf: None,
         })
@@ -174,6 +185,7 @@ impl Material for Dielectric {
         let cosine;
         let outward_normal;
         let specular_ray;
+        let transmission;
         if dot(&r_in.direction(), &hrec.normal) > 0. {
             outward_normal = -hrec.normal;
             ni_over_nt = self.ref_idx;
@@ -193,11 +205,14 @@ impl Material for Dielectric {
         }
         if rng.rand64() < reflect_prob {
             specular_ray = Ray::new(hrec.p, reflected);
+            transmission = false;
         } else {
             specular_ray = Ray::new(hrec.p, refracted);
+            transmission = true;
         }
         return Some(ScatterRecord{
             specular_ray: Some(specular_ray),
+            transmission: transmission,
             attenuation: Vec3::new(1., 1., 1.),
             pdf: None
 
@@ -222,24 +237,34 @@ impl Material for DiffuseLight {
             return Vec3::zero();
         }
     }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
 }
 
-/*
+/// Phase function for participating media (see `ConstantMedium`), which
+/// scatters equally in all directions.
 #[derive(Debug, new)]
 pub struct Isotropic {
     albedo: Box<Texture>
 }
 
 impl Material for Isotropic {
-    #[allow(unused)]
     fn scatter(&self,
                rng: &mut Rng,
                r_in: &Ray<f64>,
                rec: &HitRecord)
                -> Option<(ScatterRecord)> {
-        let scattered = Ray::new(rec.p, random_in_unit_sphere(rng));
-        let attenuation = self.albedo.value(rec.u, rec.v, &rec.p);
-        return Some((scattered, attenuation, 0.));
+        return Some(ScatterRecord {
+            specular_ray: None,
+            transmission: false,
+            attenuation: self.albedo.value(rec.u, rec.v, &rec.p),
+            pdf: Some(Box::new(SpherePdf::new())),
+        });
+    }
+
+    fn scattering_pdf(&self, r_in: &Ray<f64>, rec: &HitRecord, scattered: &Ray<f64>) -> f64 {
+        1. / (4. * PI)
     }
 }
-*/
@@ -0,0 +1,238 @@
+use std::ops::*;
+use vec3::*;
+
+/// A 4x4 matrix for affine transforms.
+///
+/// Stored in row-major order, and applied to column vectors, so
+/// `a * b` is the transform that applies `b` first and then `a`.
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct Matrix4 {
+    pub m: [[f64; 4]; 4],
+}
+
+impl Matrix4 {
+    pub fn new(m: [[f64; 4]; 4]) -> Matrix4 {
+        Matrix4 { m: m }
+    }
+
+    pub fn identity() -> Matrix4 {
+        Matrix4::new([[1., 0., 0., 0.],
+                      [0., 1., 0., 0.],
+                      [0., 0., 1., 0.],
+                      [0., 0., 0., 1.]])
+    }
+
+    pub fn translation(offset: &Vec3<f64>) -> Matrix4 {
+        Matrix4::new([[1., 0., 0., offset.x],
+                      [0., 1., 0., offset.y],
+                      [0., 0., 1., offset.z],
+                      [0., 0., 0., 1.]])
+    }
+
+    pub fn scale(s: &Vec3<f64>) -> Matrix4 {
+        Matrix4::new([[s.x, 0., 0., 0.],
+                      [0., s.y, 0., 0.],
+                      [0., 0., s.z, 0.],
+                      [0., 0., 0., 1.]])
+    }
+
+    /// Rotation about the X axis (angle in degrees).
+    pub fn rotation_x(angle: f64) -> Matrix4 {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        Matrix4::new([[1., 0., 0., 0.],
+                      [0., cos, -sin, 0.],
+                      [0., sin, cos, 0.],
+                      [0., 0., 0., 1.]])
+    }
+
+    /// Rotation about the Y axis (angle in degrees).
+    pub fn rotation_y(angle: f64) -> Matrix4 {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        Matrix4::new([[cos, 0., sin, 0.],
+                      [0., 1., 0., 0.],
+                      [-sin, 0., cos, 0.],
+                      [0., 0., 0., 1.]])
+    }
+
+    /// Rotation about the Z axis (angle in degrees).
+    pub fn rotation_z(angle: f64) -> Matrix4 {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        Matrix4::new([[cos, -sin, 0., 0.],
+                      [sin, cos, 0., 0.],
+                      [0., 0., 1., 0.],
+                      [0., 0., 0., 1.]])
+    }
+
+    /// Rotation about an arbitrary axis (angle in degrees).
+    pub fn rotation(axis: &Vec3<f64>, angle: f64) -> Matrix4 {
+        let a = axis.unit_vector();
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let t = 1. - cos;
+        Matrix4::new([[t * a.x * a.x + cos, t * a.x * a.y - sin * a.z, t * a.x * a.z + sin * a.y, 0.],
+                      [t * a.x * a.y + sin * a.z, t * a.y * a.y + cos, t * a.y * a.z - sin * a.x, 0.],
+                      [t * a.x * a.z - sin * a.y, t * a.y * a.z + sin * a.x, t * a.z * a.z + cos, 0.],
+                      [0., 0., 0., 1.]])
+    }
+
+    pub fn transpose(&self) -> Matrix4 {
+        let mut result = [[0.; 4]; 4];
+        for i in 0..4 {
+            for j in 0..4 {
+                result[i][j] = self.m[j][i];
+            }
+        }
+        Matrix4::new(result)
+    }
+
+    /// Returns None if the matrix is singular, or has entries that are
+    /// infinite or NaN.
+    pub fn inverse(&self) -> Option<Matrix4> {
+        if !self.m.iter().all(|row| row.iter().all(|x| x.is_finite())) {
+            return None;
+        }
+        // Gauss-Jordan elimination with partial pivoting.
+        let mut a = self.m;
+        let mut inv = Matrix4::identity().m;
+        for col in 0..4 {
+            let pivot = (col..4)
+                .max_by(|&i, &j| a[i][col].abs().partial_cmp(&a[j][col].abs()).unwrap())
+                .unwrap();
+            if a[pivot][col] == 0. {
+                return None;
+            }
+            a.swap(col, pivot);
+            inv.swap(col, pivot);
+            let k = 1. / a[col][col];
+            for j in 0..4 {
+                a[col][j] *= k;
+                inv[col][j] *= k;
+            }
+            for i in 0..4 {
+                if i != col {
+                    let f = a[i][col];
+                    for j in 0..4 {
+                        a[i][j] -= f * a[col][j];
+                        inv[i][j] -= f * inv[col][j];
+                    }
+                }
+            }
+        }
+        // Nearly singular matrices can still overflow.
+        if !inv.iter().all(|row| row.iter().all(|x| x.is_finite())) {
+            return None;
+        }
+        Some(Matrix4::new(inv))
+    }
+
+    /// Determinant of the upper-left 3x3 part (the linear part of an affine
+    /// transform).
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1]) -
+        m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0]) +
+        m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    /// Transform a point (includes the translation).
+    pub fn transform_point(&self, p: &Vec3<f64>) -> Vec3<f64> {
+        let m = &self.m;
+        let x = m[0][0] * p.x + m[0][1] * p.y + m[0][2] * p.z + m[0][3];
+        let y = m[1][0] * p.x + m[1][1] * p.y + m[1][2] * p.z + m[1][3];
+        let z = m[2][0] * p.x + m[2][1] * p.y + m[2][2] * p.z + m[2][3];
+        let w = m[3][0] * p.x + m[3][1] * p.y + m[3][2] * p.z + m[3][3];
+        if w == 1. {
+            Vec3::new(x, y, z)
+        } else {
+            Vec3::new(x, y, z) / w
+        }
+    }
+
+    /// Transform a direction (ignores the translation).
+    pub fn transform_vector(&self, v: &Vec3<f64>) -> Vec3<f64> {
+        let m = &self.m;
+        Vec3::new(m[0][0] * v.x + m[0][1] * v.y + m[0][2] * v.z,
+                  m[1][0] * v.x + m[1][1] * v.y + m[1][2] * v.z,
+                  m[2][0] * v.x + m[2][1] * v.y + m[2][2] * v.z)
+    }
+}
+
+impl Default for Matrix4 {
+    fn default() -> Matrix4 {
+        Matrix4::identity()
+    }
+}
+
+impl Mul for Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, rhs: Matrix4) -> Matrix4 {
+        &self * &rhs
+    }
+}
+
+impl<'a, 'b> Mul<&'a Matrix4> for &'b Matrix4 {
+    type Output = Matrix4;
+
+    fn mul(self, rhs: &'a Matrix4) -> Matrix4 {
+        let mut result = [[0.; 4]; 4];
+        for i in 0..4 {
+            for j in 0..4 {
+                for k in 0..4 {
+                    result[i][j] += self.m[i][k] * rhs.m[k][j];
+                }
+            }
+        }
+        Matrix4::new(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_near(a: &Vec3<f64>, b: &Vec3<f64>) {
+        assert!((a - b).length() < 1e-9, "{:?} != {:?}", a, b);
+    }
+
+    #[test]
+    fn test_transform() {
+        let p = Vec3::new(1.0, 2.0, 3.0);
+        let t = Matrix4::translation(&Vec3::new(1.0, 1.0, 1.0));
+        assert_eq!(t.transform_point(&p), Vec3::new(2.0, 3.0, 4.0));
+        assert_eq!(t.transform_vector(&p), p);
+        let s = Matrix4::scale(&Vec3::new(2.0, 3.0, 4.0));
+        assert_eq!(s.transform_point(&p), Vec3::new(2.0, 6.0, 12.0));
+        assert_eq!(s.determinant3(), 24.0);
+        // Scale first, then translate.
+        assert_eq!((t * s).transform_point(&p), Vec3::new(3.0, 7.0, 13.0));
+
+        let x = Vec3::new(1.0, 0.0, 0.0);
+        let y = Vec3::new(0.0, 1.0, 0.0);
+        let z = Vec3::new(0.0, 0.0, 1.0);
+        assert_near(&Matrix4::rotation_x(90.).transform_vector(&y), &z);
+        assert_near(&Matrix4::rotation_y(90.).transform_vector(&z), &x);
+        assert_near(&Matrix4::rotation_z(90.).transform_vector(&x), &y);
+        let r = Matrix4::rotation(&Vec3::new(1.0, 1.0, 1.0), 120.);
+        assert_near(&r.transform_vector(&x), &y);
+        assert_near(&r.transform_vector(&y), &z);
+    }
+
+    #[test]
+    fn test_inverse() {
+        let m = Matrix4::translation(&Vec3::new(1.0, -2.0, 3.0)) *
+                Matrix4::rotation(&Vec3::new(1.0, 2.0, 3.0), 33.) *
+                Matrix4::scale(&Vec3::new(2.0, 0.5, 4.0));
+        let inv = m.inverse().unwrap();
+        let id = m * inv;
+        for i in 0..4 {
+            for j in 0..4 {
+                let expected = if i == j { 1. } else { 0. };
+                assert!((id.m[i][j] - expected).abs() < 1e-12);
+            }
+        }
+        assert_eq!(m.transpose().transpose(), m);
+        assert!(Matrix4::scale(&Vec3::new(1.0, 0.0, 1.0)).inverse().is_none());
+        assert!(Matrix4::scale(&Vec3::new(1.0, f64::NAN, 1.0)).inverse().is_none());
+        assert!(Matrix4::translation(&Vec3::new(f64::INFINITY, 0.0, 0.0)).inverse().is_none());
+    }
+}
@@ -4,6 +4,8 @@ use hitable::*;
 use material::*;
 use util::*;
 use aabb::*;
+use onb::*;
+use pdf::*;
 
 #[derive(Debug)]
 pub struct MovingSphere {
@@ -16,7 +18,7 @@ pub struct MovingSphere {
     /// Timestamp where it is positioned at center1.
     time1: f64,
     radius: f64,
-    material: Rc<Material>,
+    material: Arc<Material>,
 }
 
 impl MovingSphere {
@@ -25,7 +27,7 @@ impl MovingSphere {
                t0: f64,
                t1: f64,
                r: f64,
-               material: Rc<Material>)
+               material: Arc<Material>)
                -> MovingSphere {
         MovingSphere {
             center0: cen0,
@@ -42,6 +44,12 @@ impl MovingSphere {
         self.center0 +
         ((time - self.time0) / (self.time1 - self.time0)) * (self.center1 - self.center0)
     }
+
+    /// A sphere around the whole path from time0 to time1.
+    fn bounding_sphere(&self) -> (Vec3<f64>, f64) {
+        (0.5 * (self.center0 + self.center1),
+         0.5 * (self.center1 - self.center0).length() + self.radius)
+    }
 }
 
 impl Hitable for MovingSphere {
@@ -84,4 +92,48 @@ impl Hitable for MovingSphere {
         return Some(surrounding_box(&box0, &box1));
     }
 
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    // The time of the ray isn't known when sampling, so directions are
+    // picked in the cone around `bounding_sphere` (or in every direction
+    // from inside it), which covers the sphere wherever it is.
+    fn pdf_value(&self, _: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        let (center, radius) = self.bounding_sphere();
+        let direction = center - *o;
+        let distance_squared = direction.squared_length();
+        if distance_squared <= radius * radius {
+            return 1. / (4. * PI);
+        }
+        let cos_theta_max = (1. - radius * radius / distance_squared).sqrt();
+        if dot(&direction, v) < cos_theta_max * distance_squared.sqrt() * v.length() {
+            return 0.;
+        }
+        1. / (2. * PI * (1. - cos_theta_max))
+    }
+
+    fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> {
+        let (center, radius) = self.bounding_sphere();
+        let direction = center - *o;
+        let distance_squared = direction.squared_length();
+        if distance_squared <= radius * radius {
+            return random_on_unit_sphere(rng);
+        }
+        Onb::new_from_w(&direction).local_vec(&random_to_sphere(rng, radius, distance_squared))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use texture::*;
+
+    #[test]
+    fn test_sampling() {
+        let m: Arc<Material> = Arc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(1., 1., 1.)))));
+        let sphere = MovingSphere::new(Vec3::new(0., 1., 0.), Vec3::new(1., 1.5, 0.), 0., 1., 0.5, m);
+        check_sampling(&sphere, &Vec3::new(0.5, -2., 1.));
+        check_sampling(&sphere, &Vec3::new(0.5, 1.2, 0.));
+    }
 }
@@ -0,0 +1,686 @@
+/*
+Wavefront OBJ/MTL import.
+
+Supports the parts of the format that matter for rendering: vertex
+positions (`v`), normals (`vn`), texture coordinates (`vt`), polygonal
+faces (`f`, triangulated as a fan, negative indices allowed), material
+libraries (`mtllib`) and material assignment (`usemtl`).  Groups, objects,
+smoothing groups, curves and so on are ignored.
+
+Faces are collected into one `TriangleMesh` per material, and sampled as one
+surface (each mesh in proportion to its area) when used as a light.  MTL materials are
+mapped onto the materials this renderer has:
+
+* `Ke` (non-black): `DiffuseLight`.
+* `d` less than 1 (or `Tr` greater than 0), or a refraction `illum` model:
+  `Dielectric` with the index of refraction from `Ni`.
+* `Ks` (non-black), when `Kd` is black or `illum` is 3: `Metal`, with the
+  fuzz derived from the `Ns` exponent.
+* Otherwise: `Lambertian` with `map_Kd` as an `ImageTexture`, or `Kd` as a
+  `ConstantTexture`.
+*/
+
+use vec3::*;
+use ray::Ray;
+use aabb::*;
+use hitable::*;
+use triangle::*;
+use material::*;
+use texture::*;
+use util::*;
+use image;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// An error loading an OBJ or MTL file.
+#[derive(Debug)]
+pub struct ObjError {
+    /// The file with the problem.
+    file: String,
+    /// Line (1-based) in the file where the problem is, if known.
+    line: Option<usize>,
+    message: String,
+}
+
+impl ObjError {
+    fn new<S: Into<String>>(file: &str, line: Option<usize>, message: S) -> ObjError {
+        ObjError {
+            file: file.to_string(),
+            line: line,
+            message: message.into(),
+        }
+    }
+
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+}
+
+impl Error for ObjError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(fmt, "{}:{}: {}", self.file, line, self.message),
+            None => write!(fmt, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+/// Load an OBJ file.
+///
+/// If `material` is given it is used for every face, and any material
+/// libraries are ignored.  Otherwise the faces use the materials from the
+/// file's material libraries (faces without a material are light gray
+/// `Lambertian`).
+pub fn load_obj<P: AsRef<Path>>(path: P, material: Option<Arc<Material>>) -> Result<Box<Hitable>, ObjError> {
+    let path = path.as_ref();
+    let source = read_file(path)?;
+    let base_dir = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    let mut loader = Loader::new(base_dir);
+    loader.obj(&source, &path.display().to_string(), material)
+}
+
+/// Build an object from the text of an OBJ file.
+///
+/// Material libraries are relative to `base_dir`.  See `load_obj`.
+pub fn parse_obj(source: &str, base_dir: &Path, material: Option<Arc<Material>>) -> Result<Box<Hitable>, ObjError> {
+    let mut loader = Loader::new(base_dir.to_path_buf());
+    loader.obj(source, "<obj>", material)
+}
+
+fn read_file(path: &Path) -> Result<String, ObjError> {
+    let mut source = String::new();
+    File::open(path)
+        .and_then(|mut f| f.read_to_string(&mut source))
+        .map_err(|e| ObjError::new(&path.display().to_string(), None, e.to_string()))?;
+    Ok(source)
+}
+
+/// A material as written in an MTL file.
+#[derive(Debug, Clone)]
+struct MtlDesc {
+    kd: Vec3<f64>,
+    map_kd: Option<String>,
+    ks: Vec3<f64>,
+    ns: f64,
+    ke: Vec3<f64>,
+    ni: Option<f64>,
+    d: f64,
+    illum: Option<u32>,
+}
+
+impl Default for MtlDesc {
+    fn default() -> MtlDesc {
+        MtlDesc {
+            kd: Vec3::new(0.8, 0.8, 0.8),
+            map_kd: None,
+            ks: Vec3::zero(),
+            ns: 0.,
+            ke: Vec3::zero(),
+            ni: None,
+            d: 1.,
+            illum: None,
+        }
+    }
+}
+
+fn is_black(c: &Vec3<f64>) -> bool {
+    c.x <= 0. && c.y <= 0. && c.z <= 0.
+}
+
+/// Triangles for one material, with the OBJ vertices (which index positions,
+/// UVs and normals separately) turned into unified vertices.
+struct MeshBuilder {
+    material: Arc<Material>,
+    positions: Vec<Vec3<f64>>,
+    normals: Vec<Vec3<f64>>,
+    uvs: Vec<(f64, f64)>,
+    triangles: Vec<[usize; 3]>,
+    /// Unified vertex for each (position, uv, normal) combination.
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    /// If any vertex is missing a normal or UV, the mesh doesn't use them.
+    missing_normals: bool,
+    missing_uvs: bool,
+}
+
+impl MeshBuilder {
+    fn new(material: Arc<Material>) -> MeshBuilder {
+        MeshBuilder {
+            material: material,
+            positions: Vec::new(),
+            normals: Vec::new(),
+            uvs: Vec::new(),
+            triangles: Vec::new(),
+            vertices: HashMap::new(),
+            missing_normals: false,
+            missing_uvs: false,
+        }
+    }
+
+    fn vertex(&mut self, obj: &ObjData, key: (usize, Option<usize>, Option<usize>)) -> usize {
+        if let Some(&i) = self.vertices.get(&key) {
+            return i;
+        }
+        let (p, t, n) = key;
+        self.positions.push(obj.positions[p]);
+        match t {
+            Some(t) => self.uvs.push(obj.uvs[t]),
+            None => {
+                self.missing_uvs = true;
+                self.uvs.push((0., 0.));
+            }
+        }
+        match n {
+            Some(n) => self.normals.push(obj.normals[n]),
+            None => {
+                self.missing_normals = true;
+                self.normals.push(Vec3::zero());
+            }
+        }
+        let i = self.positions.len() - 1;
+        self.vertices.insert(key, i);
+        i
+    }
+
+    fn build(mut self) -> TriangleMesh {
+        if self.missing_normals {
+            self.normals.clear();
+        }
+        if self.missing_uvs {
+            self.uvs.clear();
+        }
+        TriangleMesh::new(self.positions, self.normals, self.uvs, self.triangles, self.material)
+    }
+}
+
+/// The meshes for the materials of one OBJ file.
+#[derive(Debug)]
+struct MeshGroup {
+    /// Shared so that the meshes that emit can go in a scene's list of
+    /// lights.
+    meshes: Vec<Arc<TriangleMesh>>,
+    /// Running total of the areas of the meshes that are sampled, for
+    /// picking one with probability proportional to its area.  Only meshes
+    /// that emit are sampled (all of them if none do).
+    area_cdf: Vec<f64>,
+}
+
+impl MeshGroup {
+    fn new(meshes: Vec<TriangleMesh>) -> MeshGroup {
+        let emissive = meshes.iter().any(|mesh| mesh.is_emissive());
+        let mut total = 0.;
+        let area_cdf = meshes.iter().map(|mesh| {
+            if !emissive || mesh.is_emissive() {
+                total += mesh.area();
+            }
+            total
+        }).collect();
+        MeshGroup {
+            meshes: meshes.into_iter().map(Arc::new).collect(),
+            area_cdf: area_cdf,
+        }
+    }
+
+    /// Total area of the meshes that are sampled.
+    fn area(&self) -> f64 {
+        *self.area_cdf.last().unwrap()
+    }
+
+    /// Area of mesh `index`, if it is sampled, or 0.
+    fn sampled_area(&self, index: usize) -> f64 {
+        if index == 0 {
+            self.area_cdf[0]
+        } else {
+            self.area_cdf[index] - self.area_cdf[index - 1]
+        }
+    }
+}
+
+impl Hitable for MeshGroup {
+    fn hit(&self, rng: &mut Rng, r: &Ray<f64>, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let mut result = None;
+        let mut closest = t_max;
+        for mesh in &self.meshes {
+            if let Some(rec) = mesh.hit(rng, r, t_min, closest) {
+                closest = rec.t;
+                result = Some(rec);
+            }
+        }
+        result
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        let boxes = self.meshes.iter().map(|mesh| mesh.bounding_box(t0, t1).unwrap());
+        boxes.fold(None, |acc, bbox| match acc {
+            Some(acc) => Some(surrounding_box(&acc, &bbox)),
+            None => Some(bbox),
+        })
+    }
+
+    fn pdf_value(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        let total = self.area();
+        let mut sum = 0.;
+        for (i, mesh) in self.meshes.iter().enumerate() {
+            let area = self.sampled_area(i);
+            if area > 0. {
+                sum += area / total * mesh.pdf_value(rng, o, v);
+            }
+        }
+        sum
+    }
+
+    fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> {
+        let x = rng.rand64() * self.area();
+        let index = self.area_cdf.iter().position(|&a| x < a).unwrap_or(self.meshes.len() - 1);
+        self.meshes[index].random(rng, o)
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.meshes.iter().any(|mesh| mesh.is_emissive())
+    }
+
+    fn add_lights(&self, lights: &mut Vec<Arc<Hitable>>) -> bool {
+        for mesh in &self.meshes {
+            if mesh.is_emissive() {
+                lights.push(mesh.clone());
+            }
+        }
+        true
+    }
+}
+
+/// The vertex attributes read so far.
+#[derive(Default)]
+struct ObjData {
+    positions: Vec<Vec3<f64>>,
+    normals: Vec<Vec3<f64>>,
+    uvs: Vec<(f64, f64)>,
+}
+
+struct Loader {
+    base_dir: PathBuf,
+    /// Materials from the material libraries (by name).
+    materials: HashMap<String, Arc<Material>>,
+    /// Images that have been loaded so far (by path).
+    images: HashMap<PathBuf, image::DynamicImage>,
+}
+
+impl Loader {
+    fn new(base_dir: PathBuf) -> Loader {
+        Loader {
+            base_dir: base_dir,
+            materials: HashMap::new(),
+            images: HashMap::new(),
+        }
+    }
+
+    fn obj(&mut self, source: &str, file: &str, material: Option<Arc<Material>>) -> Result<Box<Hitable>, ObjError> {
+        let default_material = material.clone().unwrap_or_else(|| {
+            Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.8, 0.8, 0.8)))))
+        });
+        let mut data = ObjData::default();
+        // Meshes in the order their material was first used.
+        let mut meshes: Vec<(Option<String>, MeshBuilder)> = Vec::new();
+        let mut current: Option<String> = None;
+
+        for (i, line) in source.lines().enumerate() {
+            let line_num = i + 1;
+            let err = |msg: String| ObjError::new(file, Some(line_num), msg);
+            let line = match line.find('#') {
+                Some(pos) => &line[..pos],
+                None => line,
+            };
+            let mut words = line.split_whitespace();
+            let keyword = match words.next() {
+                Some(keyword) => keyword,
+                None => continue,
+            };
+            let args: Vec<&str> = words.collect();
+            match keyword {
+                "v" => {
+                    let v = parse_floats(&args, 3, 4).map_err(&err)?;
+                    data.positions.push(Vec3::new(v[0], v[1], v[2]));
+                },
+                "vn" => {
+                    let v = parse_floats(&args, 3, 3).map_err(&err)?;
+                    data.normals.push(Vec3::new(v[0], v[1], v[2]));
+                },
+                "vt" => {
+                    let v = parse_floats(&args, 1, 3).map_err(&err)?;
+                    data.uvs.push((v[0], v.get(1).cloned().unwrap_or(0.)));
+                },
+                "f" => {
+                    if args.len() < 3 {
+                        return Err(err(String::from("face must have at least 3 vertices")));
+                    }
+                    let keys = args.iter()
+                        .map(|arg| parse_face_vertex(arg, &data))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(&err)?;
+                    let index = match meshes.iter().position(|&(ref name, _)| *name == current) {
+                        Some(index) => index,
+                        None => {
+                            let m = match (&material, &current) {
+                                (&None, &Some(ref name)) => {
+                                    match self.materials.get(name) {
+                                        Some(m) => m.clone(),
+                                        None => return Err(err(format!("unknown material `{}`", name))),
+                                    }
+                                },
+                                _ => default_material.clone(),
+                            };
+                            meshes.push((current.clone(), MeshBuilder::new(m)));
+                            meshes.len() - 1
+                        }
+                    };
+                    let mesh = &mut meshes[index].1;
+                    let vertices: Vec<usize> = keys.into_iter().map(|key| mesh.vertex(&data, key)).collect();
+                    for j in 1..vertices.len() - 1 {
+                        mesh.triangles.push([vertices[0], vertices[j], vertices[j + 1]]);
+                    }
+                },
+                "mtllib" => {
+                    if material.is_none() {
+                        if args.is_empty() {
+                            return Err(err(String::from("mtllib needs a file name")));
+                        }
+                        // File names may contain spaces.
+                        self.mtl_file(&args.join(" "))?;
+                    }
+                },
+                "usemtl" => {
+                    if args.is_empty() {
+                        return Err(err(String::from("usemtl needs a material name")));
+                    }
+                    current = Some(args.join(" "));
+                },
+                _ => {}
+            }
+        }
+
+        if meshes.is_empty() {
+            return Err(ObjError::new(file, None, "no faces"));
+        }
+        if meshes.len() == 1 {
+            let (_, mesh) = meshes.pop().unwrap();
+            return Ok(Box::new(mesh.build()));
+        }
+        Ok(Box::new(MeshGroup::new(meshes.into_iter().map(|(_, mesh)| mesh.build()).collect())))
+    }
+
+    /// Load the materials from an MTL file.
+    fn mtl_file(&mut self, name: &str) -> Result<(), ObjError> {
+        let path = self.base_dir.join(name);
+        let source = read_file(&path)?;
+        let descs = parse_mtl(&source, &path.display().to_string())?;
+        for (name, desc) in descs {
+            let m = self.material(&desc).map_err(|e| {
+                ObjError::new(&path.display().to_string(), None, format!("material `{}`: {}", name, e))
+            })?;
+            self.materials.insert(name, m);
+        }
+        Ok(())
+    }
+
+    fn material(&mut self, desc: &MtlDesc) -> Result<Arc<Material>, String> {
+        let refracts = match desc.illum {
+            Some(4) | Some(6) | Some(7) | Some(9) => true,
+            _ => false,
+        };
+        if !is_black(&desc.ke) {
+            Ok(Arc::new(DiffuseLight::new(Box::new(ConstantTexture::new(desc.ke)))))
+        } else if desc.d < 1. || refracts {
+            Ok(Arc::new(Dielectric::new(desc.ni.unwrap_or(1.5))))
+        } else if !is_black(&desc.ks) && (is_black(&desc.kd) || desc.illum == Some(3)) {
+            Ok(Arc::new(Metal::new(desc.ks, specular_fuzz(desc.ns))))
+        } else {
+            let texture: Box<Texture> = match desc.map_kd {
+                Some(ref map) => Box::new(ImageTexture::new(self.image(map)?)),
+                None => Box::new(ConstantTexture::new(desc.kd)),
+            };
+            Ok(Arc::new(Lambertian::new(texture)))
+        }
+    }
+
+    fn image(&mut self, path: &str) -> Result<image::DynamicImage, String> {
+        let full_path = self.base_dir.join(path);
+        if !self.images.contains_key(&full_path) {
+            let img = image::open(&full_path).map_err(|e| {
+                format!("could not load image `{}`: {}", full_path.display(), e)
+            })?;
+            self.images.insert(full_path.clone(), img);
+        }
+        Ok(self.images[&full_path].clone())
+    }
+}
+
+/// Convert a Phong specular exponent to a `Metal` fuzz (0 is a perfect
+/// mirror, 1 is very rough).
+fn specular_fuzz(ns: f64) -> f64 {
+    (2. / (ns.max(0.) + 2.)).sqrt()
+}
+
+fn parse_floats(args: &[&str], min: usize, max: usize) -> Result<Vec<f64>, String> {
+    if args.len() < min || args.len() > max {
+        if min == max {
+            return Err(format!("expected {} numbers, found {}", min, args.len()));
+        }
+        return Err(format!("expected {} to {} numbers, found {}", min, max, args.len()));
+    }
+    args.iter()
+        .map(|arg| arg.parse::<f64>().map_err(|_| format!("invalid number `{}`", arg)))
+        .collect()
+}
+
+/// Convert a 1-based (or negative, relative to the end) OBJ index to a
+/// 0-based index into a list of length `len`.
+fn parse_index(s: &str, len: usize, what: &str) -> Result<usize, String> {
+    let i: i64 = s.parse().map_err(|_| format!("invalid {} index `{}`", what, s))?;
+    let index = if i > 0 {
+        i - 1
+    } else {
+        len as i64 + i
+    };
+    if i == 0 || index < 0 || index >= len as i64 {
+        return Err(format!("{} index {} out of range", what, i));
+    }
+    Ok(index as usize)
+}
+
+/// Parse a face vertex (`v`, `v/vt`, `v//vn` or `v/vt/vn`).
+fn parse_face_vertex(s: &str, data: &ObjData) -> Result<(usize, Option<usize>, Option<usize>), String> {
+    let mut parts = s.split('/');
+    let p = parse_index(parts.next().unwrap(), data.positions.len(), "vertex")?;
+    let t = match parts.next() {
+        Some("") | None => None,
+        Some(t) => Some(parse_index(t, data.uvs.len(), "texture coordinate")?),
+    };
+    let n = match parts.next() {
+        Some("") | None => None,
+        Some(n) => Some(parse_index(n, data.normals.len(), "normal")?),
+    };
+    if parts.next().is_some() {
+        return Err(format!("invalid face vertex `{}`", s));
+    }
+    Ok((p, t, n))
+}
+
+/// Parse the text of an MTL file.
+fn parse_mtl(source: &str, file: &str) -> Result<Vec<(String, MtlDesc)>, ObjError> {
+    let mut result: Vec<(String, MtlDesc)> = Vec::new();
+    for (i, line) in source.lines().enumerate() {
+        let line_num = i + 1;
+        let err = |msg: String| ObjError::new(file, Some(line_num), msg);
+        let line = match line.find('#') {
+            Some(pos) => &line[..pos],
+            None => line,
+        };
+        let mut words = line.split_whitespace();
+        let keyword = match words.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        let args: Vec<&str> = words.collect();
+        if keyword == "newmtl" {
+            if args.is_empty() {
+                return Err(err(String::from("newmtl needs a material name")));
+            }
+            result.push((args.join(" "), MtlDesc::default()));
+            continue;
+        }
+        let desc = match result.last_mut() {
+            Some(&mut (_, ref mut desc)) => desc,
+            None => return Err(err(format!("`{}` before newmtl", keyword))),
+        };
+        let color = |args: &[&str]| {
+            let c = parse_floats(args, 1, 3)?;
+            // A single value is gray.
+            match c.len() {
+                1 => Ok(Vec3::new(c[0], c[0], c[0])),
+                3 => Ok(Vec3::new(c[0], c[1], c[2])),
+                _ => Err(String::from("expected 1 or 3 numbers")),
+            }
+        };
+        match keyword {
+            "Kd" => desc.kd = color(&args).map_err(&err)?,
+            "Ks" => desc.ks = color(&args).map_err(&err)?,
+            "Ke" => desc.ke = color(&args).map_err(&err)?,
+            "Ns" => desc.ns = parse_floats(&args, 1, 1).map_err(&err)?[0],
+            "Ni" => desc.ni = Some(parse_floats(&args, 1, 1).map_err(&err)?[0]),
+            "d" => desc.d = parse_floats(&args, 1, 1).map_err(&err)?[0],
+            "Tr" => desc.d = 1. - parse_floats(&args, 1, 1).map_err(&err)?[0],
+            "illum" => {
+                desc.illum = Some(args.get(0).and_then(|s| s.parse().ok())
+                    .ok_or_else(|| err(String::from("illum needs a number")))?);
+            },
+            "map_Kd" => {
+                // Options such as `-s 1 1 1` come before the file name,
+                // which is assumed to be the last word.
+                match args.last() {
+                    Some(map) => desc.map_kd = Some(map.to_string()),
+                    None => return Err(err(String::from("map_Kd needs a file name"))),
+                }
+            },
+            _ => {}
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ray::Ray;
+
+    #[test]
+    fn test_parse_obj() {
+        let source = "
+# A unit square in the xy plane, as a single quad.
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+vt 0 0
+vt 1 0
+vt 1 1
+vt 0 1
+vn 0 0 1
+f 1/1/1 2/2/1 3/3/1 -1/-1/-1
+";
+        let obj = parse_obj(source, Path::new("."), None).unwrap();
+        let mut rng = Rng::from_seed(0);
+        let r = Ray::new(Vec3::new(0.25, 0.75, 1.), Vec3::new(0., 0., -1.));
+        let rec = obj.hit(&mut rng, &r, 0., f64::MAX).unwrap();
+        assert_eq!(rec.t, 1.);
+        assert_eq!(rec.normal, Vec3::new(0., 0., 1.));
+        assert!((rec.u - 0.25).abs() < 1e-12 && (rec.v - 0.75).abs() < 1e-12);
+
+        let e = parse_obj("v 0 0 0\nv 1 0 0\nf 1 2 3\n", Path::new("."), None).unwrap_err();
+        assert_eq!(e.to_string(), "<obj>:3: vertex index 3 out of range");
+        let e = parse_obj("v 0 0 0\nv 1 0 0\nv 0 1 0\nusemtl red\nf 1 2 3\n", Path::new("."), None).unwrap_err();
+        assert_eq!(e.to_string(), "<obj>:5: unknown material `red`");
+    }
+
+    #[test]
+    fn test_parse_mtl() {
+        let source = "
+newmtl light
+Ke 4 4 4
+
+newmtl glass
+Ni 1.33
+d 0.2
+
+newmtl chrome
+Kd 0 0 0
+Ks 0.9 0.9 0.9
+Ns 1000
+
+newmtl red
+Kd 0.8 0.1 0.1
+";
+        let mtls = parse_mtl(source, "test.mtl").unwrap();
+        let names: Vec<&str> = mtls.iter().map(|&(ref name, _)| name.as_str()).collect();
+        assert_eq!(names, ["light", "glass", "chrome", "red"]);
+        assert_eq!(mtls[1].1.ni, Some(1.33));
+        assert_eq!(mtls[3].1.kd, Vec3::new(0.8, 0.1, 0.1));
+
+        let mut loader = Loader::new(PathBuf::new());
+        let kinds: Vec<String> = mtls.iter()
+            .map(|&(_, ref desc)| {
+                let m = loader.material(desc).unwrap();
+                format!("{:?}", m).split(|c: char| !c.is_alphanumeric()).next().unwrap().to_string()
+            })
+            .collect();
+        assert_eq!(kinds, ["DiffuseLight", "Dielectric", "Metal", "Lambertian"]);
+
+        let e = parse_mtl("Kd 1 1 1\n", "test.mtl").unwrap_err();
+        assert_eq!(e.to_string(), "test.mtl:1: `Kd` before newmtl");
+    }
+
+    #[test]
+    fn test_material_sampling() {
+        // A small light next to a large white square.
+        let source = "
+v 0 0 0
+v 0.5 0 0
+v 0.5 0.5 0
+v 0 0.5 0
+v 1 0 0
+v 3 0 0
+v 3 2 0
+v 1 2 0
+usemtl light
+f 1 2 3 4
+usemtl white
+f 5 6 7 8
+";
+        let mut loader = Loader::new(PathBuf::new());
+        loader.materials.insert(String::from("light"),
+                                Arc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(4., 4., 4.))))));
+        loader.materials.insert(String::from("white"),
+                                Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.8, 0.8, 0.8))))));
+        let obj = loader.obj(source, "<obj>", None).unwrap();
+        assert!(obj.is_emissive());
+        let o = Vec3::new(0.5, 0.5, 0.5);
+        check_sampling(&*obj, &o);
+
+        // Only the light is sampled, not the white square next to it.
+        let mut rng = Rng::from_seed(0);
+        for _ in 0..1000 {
+            let v = obj.random(&mut rng, &o);
+            let rec = obj.hit(&mut rng, &Ray::new(o, v), 0.001, f64::MAX).unwrap();
+            assert!(rec.material.is_emissive());
+            assert!(rec.p.x < 0.75);
+        }
+    }
+}
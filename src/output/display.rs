@@ -0,0 +1,124 @@
+/*
+Conversion of the renderer's linear radiance into display colors.
+
+The 8-bit outputs (and the GUI) run every pixel through exposure, a tone
+mapping operator, and the sRGB transfer function.  The HDR outputs skip all
+of this and store the linear values.
+*/
+
+use std::str::FromStr;
+use vec3::*;
+use super::error::Error;
+
+/// Operators for compressing unbounded radiance into the 0-1 display range.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMap {
+    /// Clip anything brighter than 1.
+    Clamp,
+    /// Reinhard's `c / (1 + c)`.
+    Reinhard,
+    /// Narkowicz's fit of the ACES filmic curve.
+    Aces,
+}
+
+impl FromStr for ToneMap {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ToneMap, Error> {
+        match s {
+            "clamp" => Ok(ToneMap::Clamp),
+            "reinhard" => Ok(ToneMap::Reinhard),
+            "aces" => Ok(ToneMap::Aces),
+            _ => Err(Error::UnsupportedToneMap),
+        }
+    }
+}
+
+impl ToneMap {
+    fn apply(&self, c: f64) -> f64 {
+        match *self {
+            ToneMap::Clamp => c,
+            ToneMap::Reinhard => c / (1. + c),
+            ToneMap::Aces => {
+                let (a, b, c2, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                (c * (a * c + b)) / (c * (c2 * c + d) + e)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DisplaySettings {
+    /// Exposure adjustment in stops (each stop doubles the brightness).
+    pub exposure: f64,
+    pub tone_map: ToneMap,
+}
+
+impl Default for DisplaySettings {
+    fn default() -> DisplaySettings {
+        DisplaySettings {
+            exposure: 0.,
+            tone_map: ToneMap::Clamp,
+        }
+    }
+}
+
+impl DisplaySettings {
+    /// Convert a linear color to sRGB encoded values in the 0-1 range.
+    pub fn display_color(&self, color: &Vec3<f64>) -> Vec3<f64> {
+        let scale = 2f64.powf(self.exposure);
+        let channel = |c: f64| {
+            // max/min also take care of NaN from a bad sample.
+            let c = self.tone_map.apply((c * scale).max(0.));
+            srgb_encode(c.min(1.))
+        };
+        Vec3::new(channel(color.x), channel(color.y), channel(color.z))
+    }
+
+    /// Convert a linear color to 8-bit sRGB.
+    pub fn to_rgb8(&self, color: &Vec3<f64>) -> [u8; 3] {
+        let color = self.display_color(color);
+        let to_u8 = |c: f64| (c * 255. + 0.5) as u8;
+        [to_u8(color.x), to_u8(color.y), to_u8(color.z)]
+    }
+}
+
+/// The sRGB transfer function.
+fn srgb_encode(c: f64) -> f64 {
+    if c <= 0.0031308 {
+        c * 12.92
+    } else {
+        1.055 * c.powf(1. / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_color() {
+        let display = DisplaySettings::default();
+        assert_eq!(display.to_rgb8(&Vec3::new(0., 0.5, 1.)), [0, 188, 255]);
+        // Bright and invalid values must not wrap around.
+        assert_eq!(display.to_rgb8(&Vec3::new(15., -1., ::std::f64::NAN)), [255, 0, 0]);
+
+        let display = DisplaySettings { exposure: 1., tone_map: ToneMap::Clamp };
+        assert_eq!(display.to_rgb8(&Vec3::new(0.25, 0.25, 0.25)), [188, 188, 188]);
+
+        let display = DisplaySettings { exposure: 0., tone_map: ToneMap::Reinhard };
+        assert_eq!(display.to_rgb8(&Vec3::new(1., 1., 1.)), [188, 188, 188]);
+        assert!(display.to_rgb8(&Vec3::new(100., 100., 100.))[0] < 255);
+
+        let display = DisplaySettings { exposure: 0., tone_map: ToneMap::Aces };
+        let mut last = 0.;
+        for i in 0..100 {
+            let c = display.display_color(&Vec3::new(i as f64 / 10., 0., 0.)).x;
+            assert!(c >= last && c <= 1.);
+            last = c;
+        }
+
+        assert_eq!("aces".parse::<ToneMap>().unwrap(), ToneMap::Aces);
+        assert!("filmic".parse::<ToneMap>().is_err());
+    }
+}
@@ -7,6 +7,8 @@ pub type Result<T> = std::result::Result<T, GenericError>;
 #[derive(Debug)]
 pub enum Error {
     UnsupportedImageFormat,
+    UnsupportedToneMap,
+    InvalidFilenameTemplate(String),
     ExitRequested
 }
 
@@ -14,6 +16,8 @@ impl std_Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::UnsupportedImageFormat => "Unsupported file format",
+            Error::UnsupportedToneMap => "Unsupported tone mapping operator",
+            Error::InvalidFilenameTemplate(..) => "Invalid filename template",
             Error::ExitRequested => "Exit Requested",
         }
     }
@@ -21,6 +25,11 @@ impl std_Error for Error {
 
 impl fmt::Display for Error {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        write!(fmt, "Output Error: {}", self.description())
+        match *self {
+            Error::InvalidFilenameTemplate(ref msg) => {
+                write!(fmt, "Output Error: {}: {}", self.description(), msg)
+            },
+            _ => write!(fmt, "Output Error: {}", self.description()),
+        }
     }
 }
@@ -0,0 +1,189 @@
+/*
+OpenEXR output.
+
+Writes a single-part scanline file with no compression, and 32-bit float R,
+G and B channels holding the linear radiance (no gamma or tone mapping).
+See "The OpenEXR File Layout" for the format.
+*/
+
+use super::*;
+use std::io::prelude::*;
+use std::io::BufWriter;
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+const VERSION: u32 = 2;
+const PIXEL_TYPE_FLOAT: i32 = 2;
+const NO_COMPRESSION: u8 = 0;
+const INCREASING_Y: u8 = 0;
+/// Channels must be listed in alphabetical order.
+const CHANNELS: [&'static str; 3] = ["B", "G", "R"];
+
+#[derive(Debug)]
+pub struct ExrOutput {
+    settings: OutputSettings,
+    path: OutputPath,
+    /// RGB for each pixel, top row first.
+    buffer: Vec<f32>,
+    /// Whether there are pixels that haven't been saved yet.
+    dirty: bool,
+}
+
+impl ExrOutput {
+    pub fn new(settings: &OutputSettings, scene: &Scene) -> Result<ExrOutput> {
+        let size = (settings.width*settings.height*3) as usize;
+        Ok(ExrOutput{settings: settings.clone(),
+                     path: OutputPath::new(settings)?,
+                     buffer: vec![0.; size],
+                     dirty: false,
+        })
+    }
+}
+
+impl Output for ExrOutput {
+    fn put_pixel(&mut self, x: u32, y: u32, color: &Vec3<f64>) -> Result<()> {
+        let i = (3*(self.settings.width*y + x)) as usize;
+        self.buffer[i] = color[0] as f32;
+        self.buffer[i+1] = color[1] as f32;
+        self.buffer[i+2] = color[2] as f32;
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn set_frame(&mut self, frame: u32) {
+        self.path.set_frame(frame);
+    }
+
+    fn end_pass(&mut self, pass: u32) -> Result<()> {
+        self.path.set_pass(pass);
+        // Save after every pass so a progressive render can be stopped early.
+        self.end()
+    }
+
+    fn end(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let f = File::create(self.path.get())?;
+        let mut f = BufWriter::new(f);
+        write_exr(&mut f, self.settings.width, self.settings.height, &self.buffer)?;
+        f.into_inner()?.sync_all()?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+fn write_attribute<W: Write>(w: &mut W, name: &str, type_name: &str, value: &[u8]) -> Result<()> {
+    w.write_all(name.as_bytes())?;
+    w.write_all(&[0])?;
+    w.write_all(type_name.as_bytes())?;
+    w.write_all(&[0])?;
+    w.write_all(&(value.len() as i32).to_le_bytes())?;
+    w.write_all(value)?;
+    Ok(())
+}
+
+fn box2i(width: u32, height: u32) -> Vec<u8> {
+    let mut value = Vec::new();
+    for v in &[0, 0, width as i32 - 1, height as i32 - 1] {
+        value.extend_from_slice(&v.to_le_bytes());
+    }
+    value
+}
+
+fn header(width: u32, height: u32) -> Result<Vec<u8>> {
+    let mut chlist = Vec::new();
+    for name in &CHANNELS {
+        chlist.extend_from_slice(name.as_bytes());
+        chlist.push(0);
+        chlist.extend_from_slice(&PIXEL_TYPE_FLOAT.to_le_bytes());
+        // pLinear and reserved bytes.
+        chlist.extend_from_slice(&[0, 0, 0, 0]);
+        // x and y sampling.
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+        chlist.extend_from_slice(&1i32.to_le_bytes());
+    }
+    chlist.push(0);
+
+    let mut header = Vec::new();
+    header.extend_from_slice(&MAGIC);
+    header.extend_from_slice(&VERSION.to_le_bytes());
+    write_attribute(&mut header, "channels", "chlist", &chlist)?;
+    write_attribute(&mut header, "compression", "compression", &[NO_COMPRESSION])?;
+    write_attribute(&mut header, "dataWindow", "box2i", &box2i(width, height))?;
+    write_attribute(&mut header, "displayWindow", "box2i", &box2i(width, height))?;
+    write_attribute(&mut header, "lineOrder", "lineOrder", &[INCREASING_Y])?;
+    write_attribute(&mut header, "pixelAspectRatio", "float", &1f32.to_le_bytes())?;
+    write_attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8])?;
+    write_attribute(&mut header, "screenWindowWidth", "float", &1f32.to_le_bytes())?;
+    header.push(0);
+    Ok(header)
+}
+
+/// Write an image as EXR.  `pixels` is RGB for each pixel, top row first.
+fn write_exr<W: Write>(w: &mut W, width: u32, height: u32, pixels: &[f32]) -> Result<()> {
+    let header = header(width, height)?;
+    w.write_all(&header)?;
+
+    // Without compression every chunk is a single scanline: the y
+    // coordinate, the size of the data, then each channel's values for the
+    // whole line.
+    let line_size = (width as usize) * CHANNELS.len() * 4;
+    let chunk_size = 4 + 4 + line_size;
+    let table_end = header.len() + (height as usize) * 8;
+    for y in 0..height as usize {
+        let offset = (table_end + y * chunk_size) as u64;
+        w.write_all(&offset.to_le_bytes())?;
+    }
+    let mut line = Vec::with_capacity(line_size);
+    for y in 0..height as usize {
+        line.clear();
+        // B, G, R
+        for c in (0..3).rev() {
+            for x in 0..width as usize {
+                let value = pixels[3*(y*width as usize + x) + c];
+                line.extend_from_slice(&value.to_le_bytes());
+            }
+        }
+        w.write_all(&(y as i32).to_le_bytes())?;
+        w.write_all(&(line_size as i32).to_le_bytes())?;
+        w.write_all(&line)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_u64(data: &[u8], at: usize) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&data[at..at+8]);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn read_f32(data: &[u8], at: usize) -> f32 {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&data[at..at+4]);
+        f32::from_le_bytes(bytes)
+    }
+
+    #[test]
+    fn test_write_exr() {
+        // 2x2 image, with values above 1 that an 8-bit format would lose.
+        let pixels = [1., 2., 3.,   4., 5., 6.,
+                      7., 8., 9.,   10., 11., 12.5];
+        let mut data = Vec::new();
+        write_exr(&mut data, 2, 2, &pixels).unwrap();
+        assert_eq!(&data[..4], &MAGIC);
+        // The offset table follows the header.
+        let table = header(2, 2).unwrap().len();
+        assert_eq!(read_u64(&data, table), (table + 16) as u64);
+        let chunk0 = read_u64(&data, table) as usize;
+        let chunk1 = read_u64(&data, table + 8) as usize;
+        // y, size, then 2 pixels of B, G, R.
+        assert_eq!(chunk1 - chunk0, 4 + 4 + 2 * 3 * 4);
+        assert_eq!(data.len(), chunk1 + 4 + 4 + 2 * 3 * 4);
+        let line1: Vec<f32> = (0..6).map(|i| read_f32(&data, chunk1 + 8 + i * 4)).collect();
+        assert_eq!(line1, [9., 12.5, 8., 11., 7., 10.]);
+    }
+}
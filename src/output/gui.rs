@@ -20,30 +20,28 @@ impl GuiOutput {
 
         })
     }
-    fn set_pixel(&mut self, x: u32, y: u32, color: &Vec3<f64>) {
+    /// Set a pixel to an 8-bit sRGB color.
+    fn set_pixel(&mut self, x: u32, y: u32, color: [u8; 3]) {
         let i = (y*self.settings.width + x) as usize;
-        let r = (color[0] * 255.99) as u32;
-        let g = (color[1] * 255.99) as u32;
-        let b = (color[2] * 255.99) as u32;
-        let c32 = r << 16 | g << 8 | b;
+        let c32 = (color[0] as u32) << 16 | (color[1] as u32) << 8 | color[2] as u32;
         self.buffer[i] = c32;
     }
-    fn vertical_line(&mut self, x: u32, y: u32, len: u32, color: &Vec3<f64>) {
+    fn vertical_line(&mut self, x: u32, y: u32, len: u32, color: [u8; 3]) {
         for y in y..y+len {
             self.set_pixel(x, y, color);
         }
     }
-    fn horizontal_line(&mut self, x: u32, y: u32, len: u32, color: &Vec3<f64>) {
+    fn horizontal_line(&mut self, x: u32, y: u32, len: u32, color: [u8; 3]) {
         for x in x..x+len {
             self.set_pixel(x, y, color);
         }
     }
     fn outline_bucket(&mut self, bucket: &Bucket) {
-        let color = Vec3::new(1., 1., 1.);
-        self.horizontal_line(bucket.x, bucket.y, bucket.width, &color);
-        self.horizontal_line(bucket.x, bucket.y+bucket.height-1, bucket.width, &color);
-        self.vertical_line(bucket.x, bucket.y, bucket.height, &color);
-        self.vertical_line(bucket.x+bucket.width-1, bucket.y, bucket.height, &color);
+        let color = [255, 255, 255];
+        self.horizontal_line(bucket.x, bucket.y, bucket.width, color);
+        self.horizontal_line(bucket.x, bucket.y+bucket.height-1, bucket.width, color);
+        self.vertical_line(bucket.x, bucket.y, bucket.height, color);
+        self.vertical_line(bucket.x+bucket.width-1, bucket.y, bucket.height, color);
     }
 }
 
@@ -56,6 +54,7 @@ impl Output for GuiOutput {
         if self.window.is_key_down(Key::Escape) {
             return Err(Error::ExitRequested.into())
         }
+        let color = self.settings.display.to_rgb8(color);
         self.set_pixel(x, y, color);
         self.window.update_with_buffer(&self.buffer);
         Ok(())
@@ -72,12 +71,8 @@ impl Output for GuiOutput {
         }
         for (y, row) in pixels.iter().enumerate() {
             for (x, pixel) in row.iter().enumerate() {
-                let i = ((bucket.y+y as u32) *self.settings.width + bucket.x+x as u32) as usize;
-                let r = (pixel[0] * 255.99) as u32;
-                let g = (pixel[1] * 255.99) as u32;
-                let b = (pixel[2] * 255.99) as u32;
-                let c32 = r << 16 | g << 8 | b;
-                self.buffer[i] = c32;
+                let color = self.settings.display.to_rgb8(pixel);
+                self.set_pixel(bucket.x+x as u32, bucket.y+y as u32, color);
             }
         }
         self.window.update_with_buffer(&self.buffer);
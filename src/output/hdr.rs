@@ -0,0 +1,139 @@
+/*
+Radiance RGBE (.hdr) output.
+
+Each pixel is stored as an 8-bit mantissa for R, G and B with a shared
+8-bit exponent, holding the linear radiance.  Scanlines are written with the
+"new" run-length encoding, which is what most readers expect.
+*/
+
+use super::*;
+use std::io::prelude::*;
+use std::io::BufWriter;
+
+/// Scanline widths that can use the run-length encoding.
+const MIN_RLE_WIDTH: u32 = 8;
+const MAX_RLE_WIDTH: u32 = 0x7fff;
+/// Longest literal dump in a run-length encoded scanline.
+const MAX_DUMP: usize = 128;
+
+#[derive(Debug)]
+pub struct HdrOutput {
+    settings: OutputSettings,
+    path: OutputPath,
+    /// RGBE for each pixel, top row first.
+    buffer: Vec<u8>,
+    /// Whether there are pixels that haven't been saved yet.
+    dirty: bool,
+}
+
+impl HdrOutput {
+    pub fn new(settings: &OutputSettings, scene: &Scene) -> Result<HdrOutput> {
+        let size = (settings.width*settings.height*4) as usize;
+        Ok(HdrOutput{settings: settings.clone(),
+                     path: OutputPath::new(settings)?,
+                     buffer: vec![0; size],
+                     dirty: false,
+        })
+    }
+}
+
+impl Output for HdrOutput {
+    fn put_pixel(&mut self, x: u32, y: u32, color: &Vec3<f64>) -> Result<()> {
+        let i = (4*(self.settings.width*y + x)) as usize;
+        self.buffer[i..i+4].copy_from_slice(&to_rgbe(color));
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn set_frame(&mut self, frame: u32) {
+        self.path.set_frame(frame);
+    }
+
+    fn end_pass(&mut self, pass: u32) -> Result<()> {
+        self.path.set_pass(pass);
+        // Save after every pass so a progressive render can be stopped early.
+        self.end()
+    }
+
+    fn end(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let f = File::create(self.path.get())?;
+        let mut f = BufWriter::new(f);
+        write_hdr(&mut f, self.settings.width, self.settings.height, &self.buffer)?;
+        f.into_inner()?.sync_all()?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Convert a linear color to RGBE.
+fn to_rgbe(color: &Vec3<f64>) -> [u8; 4] {
+    let v = color.x.max(color.y).max(color.z);
+    if !(v > 1e-32) {
+        return [0, 0, 0, 0];
+    }
+    // Split v into mantissa * 2^exp with the mantissa in [0.5, 1).
+    let mut exp = v.log2().floor() as i32 + 1;
+    if v / 2f64.powi(exp) >= 1. {
+        exp += 1;
+    }
+    let exp = exp.max(-128).min(127);
+    let scale = 256. / 2f64.powi(exp);
+    let channel = |c: f64| (c.max(0.) * scale).min(255.) as u8;
+    [channel(color.x), channel(color.y), channel(color.z), (exp + 128) as u8]
+}
+
+/// Write an image as RGBE.  `pixels` is RGBE for each pixel, top row first.
+fn write_hdr<W: Write>(w: &mut W, width: u32, height: u32, pixels: &[u8]) -> Result<()> {
+    write!(w, "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n", height, width)?;
+    let line_size = (width * 4) as usize;
+    for line in pixels.chunks(line_size) {
+        if width < MIN_RLE_WIDTH || width > MAX_RLE_WIDTH {
+            // Flat pixels.
+            w.write_all(line)?;
+            continue;
+        }
+        w.write_all(&[2, 2, (width >> 8) as u8, (width & 0xff) as u8])?;
+        // Each component is encoded separately.  Only literal dumps are
+        // used, which keeps the encoder simple at a small cost in size.
+        for c in 0..4 {
+            let component: Vec<u8> = line.iter().skip(c).step_by(4).cloned().collect();
+            for dump in component.chunks(MAX_DUMP) {
+                w.write_all(&[dump.len() as u8])?;
+                w.write_all(dump)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_hdr() {
+        assert_eq!(to_rgbe(&Vec3::new(0., 0., 0.)), [0, 0, 0, 0]);
+        assert_eq!(to_rgbe(&Vec3::new(1., 0.5, 0.25)), [128, 64, 32, 129]);
+        // Values above 1 that an 8-bit format would lose.
+        assert_eq!(to_rgbe(&Vec3::new(12., 3., 0.)), [192, 48, 0, 132]);
+
+        let pixels: Vec<u8> = (0..8).flat_map(|i| vec![i, 1, 2, 128]).collect();
+        let mut data = Vec::new();
+        write_hdr(&mut data, 8, 1, &pixels).unwrap();
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 8\n";
+        assert_eq!(&data[..header.len()], &header[..]);
+        let line = &data[header.len()..];
+        assert_eq!(&line[..4], &[2, 2, 0, 8]);
+        // Red is the first dump of 8 values.
+        assert_eq!(&line[4..13], &[8, 0, 1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(line.len(), 4 + 4 * 9);
+
+        // Narrow images are written flat.
+        let mut data = Vec::new();
+        write_hdr(&mut data, 2, 1, &pixels[..8]).unwrap();
+        assert_eq!(&data[data.len()-8..], &pixels[..8]);
+    }
+}
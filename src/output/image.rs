@@ -6,7 +6,10 @@ pub struct ImageOutput<P, Container>
 {
     // buf: image::ImageBuffer<image::Rgb<u8>,Vec<u8>>,
     buf: image::ImageBuffer<P,Container>,
-    path: String,
+    path: OutputPath,
+    display: DisplaySettings,
+    /// Whether there are pixels that haven't been saved yet.
+    dirty: bool,
 }
 
 pub type ImageOutput8 = ImageOutput<image::Rgb<u8>, Vec<u8>>;
@@ -17,21 +20,33 @@ impl<P, Container> ImageOutput<P, Container>
     pub fn new(settings: &OutputSettings, scene: &Scene) -> Result<ImageOutput8> {
         Ok(ImageOutput{
             buf: image::ImageBuffer::new(settings.width, settings.height),
-            path: path_from_template(&settings.filename_template, scene),
+            path: OutputPath::new(settings)?,
+            display: settings.display.clone(),
+            dirty: false,
         })
     }
 }
 
 impl Output for ImageOutput8 {
     fn put_pixel(&mut self, x: u32, y: u32, color: &Vec3<f64>) -> Result<()> {
-        let pixel = image::Rgb([(color.x*255.99) as u8,
-                                (color.y*255.99) as u8,
-                                (color.z*255.99) as u8]);
+        let pixel = image::Rgb(self.display.to_rgb8(color));
         self.buf.put_pixel(x, y, pixel);
+        self.dirty = true;
         Ok(())
     }
+    fn set_frame(&mut self, frame: u32) {
+        self.path.set_frame(frame);
+    }
+    fn end_pass(&mut self, pass: u32) -> Result<()> {
+        self.path.set_pass(pass);
+        // Save after every pass so a progressive render can be stopped early.
+        self.end()
+    }
     fn end(&mut self) -> Result<()> {
-        self.buf.save(&self.path)?;
+        if self.dirty {
+            self.buf.save(self.path.get())?;
+            self.dirty = false;
+        }
         Ok(())
     }
 }
@@ -1,7 +1,14 @@
 pub mod error;
 pub use self::error::*;
+mod display;
+pub use self::display::*;
+mod template;
+pub use self::template::*;
 mod ppm;
 mod image;
+mod exr;
+mod hdr;
+mod pfm;
 #[cfg(feature="gui")]
 mod gui;
 mod buckets;
@@ -24,8 +31,14 @@ pub trait Output {
         }
         Ok(())
     }
+    /// Called after every pixel in the image has been updated.  With
+    /// progressive rendering this happens once per pass.
+    fn end_pass(&mut self, pass: u32) -> Result<()> {Ok(())}
     fn end(&mut self) -> Result<()> {Ok(())}
     fn wait_to_exit(&mut self) {}
+    /// Called before rendering each frame of an animation, so one output
+    /// can be used for every frame.
+    fn set_frame(&mut self, frame: u32) {}
 }
 
 #[derive(Debug, Clone)]
@@ -35,6 +48,28 @@ pub enum OutputFormat {
     Ppm,
     Png,
     Jpg,
+    /// OpenEXR, with linear floating point values.
+    Exr,
+    /// Radiance RGBE, with linear values.
+    Hdr,
+    /// Portable Float Map, with linear floating point values.
+    Pfm,
+}
+
+impl OutputFormat {
+    /// File extension for the `{ext}` filename token.
+    pub fn extension(&self) -> &'static str {
+        match *self {
+            #[cfg(feature="gui")]
+            OutputFormat::Gui => "",
+            OutputFormat::Ppm => "ppm",
+            OutputFormat::Png => "png",
+            OutputFormat::Jpg => "jpg",
+            OutputFormat::Exr => "exr",
+            OutputFormat::Hdr => "hdr",
+            OutputFormat::Pfm => "pfm",
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -43,23 +78,38 @@ pub struct OutputSettings {
     pub filename_template: String,
     pub width: u32,
     pub height: u32,
+    /// How the 8-bit outputs convert radiance to display colors.
+    pub display: DisplaySettings,
+    /// Values for the tokens in `filename_template`.
+    pub template_values: TemplateValues,
 }
 
 impl OutputSettings {
+    /// Set the output filename, which may contain tokens such as `{frame}`
+    /// (see `template.rs`).  The format is chosen from the extension of the
+    /// filled in name.  An `{ext}` extension keeps the current format.
     pub fn set_filename_template(&mut self, tmpl: String) -> Result<()> {
-        let p = Path::new(&tmpl);
-        if let Some(ext) = p.extension() {
-            self.format = match ext.to_str().unwrap() {
-                "png" => OutputFormat::Png,
-                "jpg" => OutputFormat::Jpg,
-                "jpegg" => OutputFormat::Jpg,
-                _ => { return Err(Error::UnsupportedImageFormat.into()) }
-            };
-            self.filename_template = tmpl.clone();
-            Ok(())
-        } else {
-            Err(Error::UnsupportedImageFormat.into())
-        }
+        validate_template(&tmpl)?;
+        let mut settings = self.clone();
+        settings.filename_template = tmpl;
+        let path = OutputPath::new(&settings)?.get();
+        let format = match Path::new(&path).extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext == self.format.extension() => self.format.clone(),
+            Some("ppm") => OutputFormat::Ppm,
+            Some("png") => OutputFormat::Png,
+            Some("jpg") | Some("jpeg") => OutputFormat::Jpg,
+            Some("exr") => OutputFormat::Exr,
+            Some("hdr") => OutputFormat::Hdr,
+            Some("pfm") => OutputFormat::Pfm,
+            Some(_) => return Err(Error::UnsupportedImageFormat.into()),
+            None => {
+                let msg = format!("`{}`: no file extension", settings.filename_template);
+                return Err(Error::InvalidFilenameTemplate(msg).into());
+            },
+        };
+        self.format = format;
+        self.filename_template = settings.filename_template;
+        Ok(())
     }
 }
 
@@ -76,10 +126,8 @@ pub fn new_output(settings: &OutputSettings, scene: &Scene) -> Result<Box<Output
             let jpg = image::ImageOutput8::new(settings, scene)?;
             Ok(Box::new(jpg))
         },
+        OutputFormat::Exr => Ok(Box::new(exr::ExrOutput::new(settings, scene)?)),
+        OutputFormat::Hdr => Ok(Box::new(hdr::HdrOutput::new(settings, scene)?)),
+        OutputFormat::Pfm => Ok(Box::new(pfm::PfmOutput::new(settings, scene)?)),
     }
 }
-
-// Frame # (w/padding), Scene, RenderLayer, Camera, RenderPass, Extension (and custom), Version (custom label), date, time
-fn path_from_template(template: &str, scene: &Scene) -> String {
-    String::from(template)
-}
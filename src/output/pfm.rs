@@ -0,0 +1,98 @@
+/*
+Portable Float Map (.pfm) output.
+
+A small text header followed by 32-bit float RGB for each pixel, holding the
+linear radiance.  Rows are stored bottom to top, and a negative scale in the
+header marks the data as little-endian.
+*/
+
+use super::*;
+use std::io::prelude::*;
+use std::io::BufWriter;
+
+#[derive(Debug)]
+pub struct PfmOutput {
+    settings: OutputSettings,
+    path: OutputPath,
+    /// RGB for each pixel, top row first.
+    buffer: Vec<f32>,
+    /// Whether there are pixels that haven't been saved yet.
+    dirty: bool,
+}
+
+impl PfmOutput {
+    pub fn new(settings: &OutputSettings, scene: &Scene) -> Result<PfmOutput> {
+        let size = (settings.width*settings.height*3) as usize;
+        Ok(PfmOutput{settings: settings.clone(),
+                     path: OutputPath::new(settings)?,
+                     buffer: vec![0.; size],
+                     dirty: false,
+        })
+    }
+}
+
+impl Output for PfmOutput {
+    fn put_pixel(&mut self, x: u32, y: u32, color: &Vec3<f64>) -> Result<()> {
+        let i = (3*(self.settings.width*y + x)) as usize;
+        self.buffer[i] = color[0] as f32;
+        self.buffer[i+1] = color[1] as f32;
+        self.buffer[i+2] = color[2] as f32;
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn set_frame(&mut self, frame: u32) {
+        self.path.set_frame(frame);
+    }
+
+    fn end_pass(&mut self, pass: u32) -> Result<()> {
+        self.path.set_pass(pass);
+        // Save after every pass so a progressive render can be stopped early.
+        self.end()
+    }
+
+    fn end(&mut self) -> Result<()> {
+        if !self.dirty {
+            return Ok(());
+        }
+        let f = File::create(self.path.get())?;
+        let mut f = BufWriter::new(f);
+        write_pfm(&mut f, self.settings.width, self.settings.height, &self.buffer)?;
+        f.into_inner()?.sync_all()?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+/// Write an image as PFM.  `pixels` is RGB for each pixel, top row first.
+fn write_pfm<W: Write>(w: &mut W, width: u32, height: u32, pixels: &[f32]) -> Result<()> {
+    write!(w, "PF\n{} {}\n-1.0\n", width, height)?;
+    for line in pixels.chunks((width * 3) as usize).rev() {
+        for value in line {
+            w.write_all(&value.to_le_bytes())?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_pfm() {
+        let pixels = [1., 2., 3.,   4., 5., 6.,
+                      7., 8., 9.,   10., 11., 12.5];
+        let mut data = Vec::new();
+        write_pfm(&mut data, 2, 2, &pixels).unwrap();
+        let header = b"PF\n2 2\n-1.0\n";
+        assert_eq!(&data[..header.len()], &header[..]);
+        let values: Vec<f32> = data[header.len()..].chunks(4).map(|b| {
+            let mut bytes = [0; 4];
+            bytes.copy_from_slice(b);
+            f32::from_le_bytes(bytes)
+        }).collect();
+        // Bottom row first.
+        assert_eq!(values, [7., 8., 9., 10., 11., 12.5, 1., 2., 3., 4., 5., 6.]);
+    }
+}
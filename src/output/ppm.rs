@@ -4,16 +4,19 @@ use std::io::prelude::*;
 #[derive(Debug)]
 pub struct PpmOutput {
     settings: OutputSettings,
-    path: String,
-    buffer: Vec<u8>
+    path: OutputPath,
+    buffer: Vec<u8>,
+    /// Whether there are pixels that haven't been saved yet.
+    dirty: bool,
 }
 
 impl PpmOutput {
     pub fn new(settings: &OutputSettings, scene: &Scene) -> Result<PpmOutput> {
         let size = (settings.width*settings.height*3) as usize;
         Ok(PpmOutput{settings: settings.clone(),
-                     path: path_from_template(&settings.filename_template, scene),
+                     path: OutputPath::new(settings)?,
                      buffer: vec![0; size],
+                     dirty: false,
         })
     }
 }
@@ -21,14 +24,27 @@ impl PpmOutput {
 impl Output for PpmOutput {
     fn put_pixel(&mut self, x: u32, y: u32, color: &Vec3<f64>) -> Result<()> {
         let i = (3*(self.settings.width*y + x)) as usize;
-        self.buffer[i] = (color[0] * 255.99) as u8;
-        self.buffer[i+1] = (color[1] * 255.99) as u8;
-        self.buffer[i+2] = (color[2] * 255.99) as u8;
+        let rgb = self.settings.display.to_rgb8(color);
+        self.buffer[i..i+3].copy_from_slice(&rgb);
+        self.dirty = true;
         Ok(())
     }
 
+    fn set_frame(&mut self, frame: u32) {
+        self.path.set_frame(frame);
+    }
+
+    fn end_pass(&mut self, pass: u32) -> Result<()> {
+        self.path.set_pass(pass);
+        // Save after every pass so a progressive render can be stopped early.
+        self.end()
+    }
+
     fn end(&mut self) -> Result<()> {
-        let mut f = File::create(&self.path)?;
+        if !self.dirty {
+            return Ok(());
+        }
+        let mut f = File::create(self.path.get())?;
         writeln!(f, "P3\n{} {}\n255", self.settings.width, self.settings.height)?;
         for y in 0..self.settings.height {
             for x in 0..self.settings.width {
@@ -37,6 +53,7 @@ impl Output for PpmOutput {
             }
         }
         f.sync_all()?;
+        self.dirty = false;
         Ok(())
     }
 }
@@ -0,0 +1,273 @@
+/*
+Filename templates.
+
+Output filenames may contain tokens in braces that are filled in when the
+file is written, so that batch and animation renders don't overwrite each
+other:
+
+- `{scene}`: Name of the scene.
+- `{frame}`: Animation frame number.
+- `{pass}`: Progressive rendering pass, starting at 0.
+- `{ext}`: Extension for the output format.
+- `{layer}`: Render layer.
+- `{camera}`: Camera name.
+- `{version}`: Free-form version label.
+- `{date}`: Date the render started, as YYYY-MM-DD (UTC).
+- `{time}`: Time the render started, as HH-MM-SS (UTC).
+
+Numbers may be padded, for example `{frame:04}` gives `0007`.  Use `{{` and
+`}}` for literal braces.
+*/
+
+use std::time::{SystemTime, UNIX_EPOCH};
+use super::*;
+
+/// Values for the tokens in a filename template.
+#[derive(Debug, Clone)]
+pub struct TemplateValues {
+    pub scene: String,
+    pub frame: u32,
+    pub layer: String,
+    pub camera: String,
+    pub version: String,
+}
+
+impl Default for TemplateValues {
+    fn default() -> TemplateValues {
+        TemplateValues {
+            scene: String::from("scene"),
+            frame: 1,
+            layer: String::from("beauty"),
+            camera: String::from("camera"),
+            version: String::from("v001"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Literal(String),
+    Scene,
+    /// Frame number with zero padding width.
+    Frame(usize),
+    /// Pass number with zero padding width.
+    Pass(usize),
+    Ext,
+    Layer,
+    Camera,
+    Version,
+    Date,
+    Time,
+}
+
+fn template_error(template: &str, msg: &str) -> GenericError {
+    Error::InvalidFilenameTemplate(format!("`{}`: {}", template, msg)).into()
+}
+
+fn parse_template(template: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut literal = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            },
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            },
+            '}' => return Err(template_error(template, "unmatched `}`")),
+            '{' => {
+                let mut inner = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => inner.push(c),
+                        None => return Err(template_error(template, "unclosed `{`")),
+                    }
+                }
+                if !literal.is_empty() {
+                    tokens.push(Token::Literal(literal.clone()));
+                    literal.clear();
+                }
+                tokens.push(parse_token(template, &inner)?);
+            },
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() {
+        tokens.push(Token::Literal(literal));
+    }
+    Ok(tokens)
+}
+
+fn parse_token(template: &str, token: &str) -> Result<Token> {
+    let mut parts = token.splitn(2, ':');
+    let name = parts.next().unwrap();
+    let width = match parts.next() {
+        Some(spec) => {
+            if name != "frame" && name != "pass" {
+                return Err(template_error(template, &format!("`{{{}}}` does not take padding", name)));
+            }
+            match spec.parse::<usize>() {
+                Ok(width) => Some(width),
+                Err(_) => return Err(template_error(template, &format!("invalid padding `{}`", spec))),
+            }
+        },
+        None => None,
+    };
+    Ok(match name {
+        "scene" => Token::Scene,
+        "frame" => Token::Frame(width.unwrap_or(0)),
+        "pass" => Token::Pass(width.unwrap_or(0)),
+        "ext" => Token::Ext,
+        "layer" => Token::Layer,
+        "camera" => Token::Camera,
+        "version" => Token::Version,
+        "date" => Token::Date,
+        "time" => Token::Time,
+        _ => return Err(template_error(template, &format!("unknown token `{{{}}}`", name))),
+    })
+}
+
+/// Check that a filename template only uses known tokens.
+pub fn validate_template(template: &str) -> Result<()> {
+    parse_template(template).map(|_| ())
+}
+
+/// Convert days since 1970-01-01 to (year, month, day).
+///
+/// From Howard Hinnant's "chrono-Compatible Low-Level Date Algorithms".
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let y = yoe + era * 400 + if m <= 2 { 1 } else { 0 };
+    (y, m, d)
+}
+
+/// The path of an output file, which may change from pass to pass.
+#[derive(Debug)]
+pub struct OutputPath {
+    tokens: Vec<Token>,
+    values: TemplateValues,
+    ext: &'static str,
+    /// Seconds since the epoch when the output was created.
+    start: u64,
+    pass: u32,
+}
+
+impl OutputPath {
+    pub fn new(settings: &OutputSettings) -> Result<OutputPath> {
+        let start = SystemTime::now().duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        Ok(OutputPath {
+            tokens: parse_template(&settings.filename_template)?,
+            values: settings.template_values.clone(),
+            ext: settings.format.extension(),
+            start: start,
+            pass: 0,
+        })
+    }
+
+    pub fn set_pass(&mut self, pass: u32) {
+        self.pass = pass;
+    }
+
+    pub fn set_frame(&mut self, frame: u32) {
+        self.values.frame = frame;
+    }
+
+    /// The path with all tokens filled in.
+    pub fn get(&self) -> String {
+        let mut path = String::new();
+        let secs = self.start % 86400;
+        for token in &self.tokens {
+            match *token {
+                Token::Literal(ref s) => path.push_str(s),
+                Token::Scene => path.push_str(&self.values.scene),
+                Token::Frame(width) => path.push_str(&format!("{:01$}", self.values.frame, width)),
+                Token::Pass(width) => path.push_str(&format!("{:01$}", self.pass, width)),
+                Token::Ext => path.push_str(self.ext),
+                Token::Layer => path.push_str(&self.values.layer),
+                Token::Camera => path.push_str(&self.values.camera),
+                Token::Version => path.push_str(&self.values.version),
+                Token::Date => {
+                    let (y, m, d) = civil_from_days((self.start / 86400) as i64);
+                    path.push_str(&format!("{:04}-{:02}-{:02}", y, m, d));
+                },
+                Token::Time => {
+                    path.push_str(&format!("{:02}-{:02}-{:02}", secs / 3600, secs / 60 % 60, secs % 60));
+                },
+            }
+        }
+        path
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_template() {
+        let mut settings = OutputSettings {
+            format: OutputFormat::Png,
+            filename_template: String::from("{scene}_{frame:04}_{pass}.{ext}"),
+            width: 1,
+            height: 1,
+            display: DisplaySettings::default(),
+            template_values: TemplateValues::default(),
+        };
+        settings.template_values.scene = String::from("cornell");
+        settings.template_values.frame = 7;
+        let mut path = OutputPath::new(&settings).unwrap();
+        assert_eq!(path.get(), "cornell_0007_0.png");
+        path.set_pass(12);
+        assert_eq!(path.get(), "cornell_0007_12.png");
+
+        settings.filename_template = String::from("out/{layer}-{camera}-{version}{{1}}.exr");
+        assert_eq!(OutputPath::new(&settings).unwrap().get(), "out/beauty-camera-v001{1}.exr");
+
+        // 2017-06-05 04:03:02
+        let mut path = OutputPath::new(&settings).unwrap();
+        path.tokens = parse_template("{date}_{time}").unwrap();
+        path.start = 1496635382;
+        assert_eq!(path.get(), "2017-06-05_04-03-02");
+
+        for &(template, msg) in &[("{nope}.png", "unknown token `{nope}`"),
+                                  ("{scene:04}.png", "`{scene}` does not take padding"),
+                                  ("{frame:x}.png", "invalid padding `x`"),
+                                  ("{frame.png", "unclosed `{`"),
+                                  ("frame}.png", "unmatched `}`")] {
+            let e = validate_template(template).unwrap_err();
+            assert_eq!(e.to_string(),
+                       format!("Output Error: Invalid filename template: `{}`: {}", template, msg));
+        }
+
+        // The format comes from the extension of the filled in name.
+        settings.set_filename_template(String::from("out.{frame:04}.exr")).unwrap();
+        assert_eq!(settings.format.extension(), "exr");
+        settings.set_filename_template(String::from("{scene}.{ext}")).unwrap();
+        assert_eq!(settings.format.extension(), "exr");
+        settings.template_values.version = String::from("v1.jpg");
+        settings.set_filename_template(String::from("{version}")).unwrap();
+        assert_eq!(settings.format.extension(), "jpg");
+        settings.set_filename_template(String::from("out.ppm")).unwrap();
+        assert_eq!(settings.format.extension(), "ppm");
+        settings.set_filename_template(String::from("{version}")).unwrap();
+        let e = settings.set_filename_template(String::from("out/render_{frame}")).unwrap_err();
+        assert_eq!(e.to_string(),
+                   "Output Error: Invalid filename template: `out/render_{frame}`: no file extension");
+        assert!(settings.set_filename_template(String::from("out.{frame}")).is_err());
+        assert_eq!(settings.filename_template, "{version}");
+    }
+}
@@ -2,14 +2,15 @@ use vec3::*;
 use util::*;
 use onb::*;
 use hitable::*;
+use background::*;
 
 pub fn random_cosine_direction(rng: &mut Rng) -> Vec3<f64> {
     let r1 = rng.rand64();
     let r2 = rng.rand64();
     let z = (1.-r2).sqrt();
     let phi = 2.*PI*r1;
-    let x = phi.cos()*2.*r2.sqrt();
-    let y = phi.sin()*2.*r2.sqrt();
+    let x = phi.cos()*r2.sqrt();
+    let y = phi.sin()*r2.sqrt();
     return Vec3::new(x,y,z);
 }
 
@@ -24,6 +25,14 @@ pub fn random_to_sphere(rng: &mut Rng, radius: f64, distance_squared: f64) -> Ve
     return Vec3::new(x, y, z);
 }
 
+/// Pick a uniformly distributed direction.
+pub fn random_on_unit_sphere(rng: &mut Rng) -> Vec3<f64> {
+    let z = 1. - 2.*rng.rand64();
+    let r = (1.-z*z).sqrt();
+    let phi = 2.*PI*rng.rand64();
+    return Vec3::new(r*phi.cos(), r*phi.sin(), z);
+}
+
 pub trait Pdf: fmt::Debug {
     fn value(&self, rng: &mut Rng, direction: &Vec3<f64>) -> f64;
     fn generate(&self, rng: &mut Rng) -> Vec3<f64>;
@@ -57,6 +66,20 @@ impl Pdf for CosinePdf {
     }
 }
 
+/// Uniform over all directions.
+#[derive(Debug, new)]
+pub struct SpherePdf {}
+
+impl Pdf for SpherePdf {
+    fn value(&self, rng: &mut Rng, direction: &Vec3<f64>) -> f64 {
+        return 1./(4.*PI);
+    }
+
+    fn generate(&self, rng: &mut Rng) -> Vec3<f64> {
+        return random_on_unit_sphere(rng);
+    }
+}
+
 #[derive(Debug, new)]
 pub struct HitablePdf<'a> {
     o: Vec3<f64>,
@@ -72,6 +95,21 @@ impl<'a> Pdf for HitablePdf<'a> {
     }
 }
 
+/// Samples the background (see `Background::is_sampled`).
+#[derive(Debug, new)]
+pub struct BackgroundPdf<'a> {
+    background: &'a Background,
+}
+
+impl<'a> Pdf for BackgroundPdf<'a> {
+    fn value(&self, rng: &mut Rng, direction: &Vec3<f64>) -> f64 {
+        return self.background.pdf_value(direction);
+    }
+    fn generate(&self, rng: &mut Rng) -> Vec3<f64> {
+        return self.background.random(rng);
+    }
+}
+
 #[derive(Debug, new)]
 pub struct MixturePdf<'a, 'b> {
     pdf0: &'a Pdf,
@@ -90,3 +128,34 @@ impl<'a, 'b> Pdf for MixturePdf<'a, 'b> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cosine_pdf() {
+        let mut rng = Rng::from_seed(0);
+        let pdf = CosinePdf::new(&Vec3::new(1., 2., -1.));
+        let w = Vec3::new(1., 2., -1.).unit_vector();
+        let n = 200000;
+        // Integrates to one over the sphere of directions.
+        let mut total = 0.;
+        for _ in 0..n {
+            let v = random_on_unit_sphere(&mut rng);
+            total += pdf.value(&mut rng, &v) * 4. * PI;
+        }
+        assert!((total / n as f64 - 1.).abs() < 0.01, "{}", total / n as f64);
+        // Generated directions follow it: unit length, on the side of `w`,
+        // with a mean cosine of 2/3.
+        let mut cos_total = 0.;
+        for _ in 0..n {
+            let v = pdf.generate(&mut rng);
+            assert!((v.length() - 1.).abs() < 1e-9);
+            let cosine = dot(&v, &w);
+            assert!(cosine >= 0.);
+            cos_total += cosine;
+        }
+        assert!((cos_total / n as f64 - 2. / 3.).abs() < 0.01, "{}", cos_total / n as f64);
+    }
+}
@@ -1,8 +1,7 @@
 #![allow(non_snake_case)]
-extern crate rand;
 
 use vec3::*;
-use rand::Rng;
+use util::*;
 use std::sync;
 
 static mut PERM_X: [u8; 256] = [0; 256];
@@ -18,15 +17,19 @@ static mut RANVEC: [Vec3<f64>; 256] = [Vec3{x:0.,y:0.,z:0.}; 256];
 // precompute the values and place the literals directly in this rs file.
 
 /// Initialize noise.  Must be called once (in main).
-pub fn perlin_init() {
+///
+/// The tables are generated from `seed`, so the noise is the same for every
+/// render with the same seed.  Only the first call has any effect.
+pub fn perlin_init(seed: u64) {
     unsafe {
         static mut ONCE: sync::Once = sync::ONCE_INIT;
         ONCE.call_once(|| {
-            PERM_X = perlin_generate_perm();
-            PERM_Y = perlin_generate_perm();
-            PERM_Z = perlin_generate_perm();
-            RANFLOAT = perlin_generate_float();
-            RANVEC = perlin_generate();
+            let mut rng = Rng::from_seed(seed);
+            PERM_X = perlin_generate_perm(&mut rng);
+            PERM_Y = perlin_generate_perm(&mut rng);
+            PERM_Z = perlin_generate_perm(&mut rng);
+            RANFLOAT = perlin_generate_float(&mut rng);
+            RANVEC = perlin_generate(&mut rng);
         });
     }
 }
@@ -38,44 +41,41 @@ fn get_perm_z() -> &'static [u8; 256] {unsafe { &PERM_Z }}
 fn get_ranfloat() -> &'static [f64; 256] {unsafe { &RANFLOAT }}
 fn get_ranvec() -> &'static [Vec3<f64>; 256] {unsafe { &RANVEC }}
 
-fn perlin_generate_perm() -> [u8; 256] {
+fn perlin_generate_perm(rng: &mut Rng) -> [u8; 256] {
     let mut result = [0u8; 256];
     for i in 0..256 {
         result[i] = i as u8;
     }
     // rand::thread_rng().shuffle(result)
-    permute(&mut result);
+    permute(rng, &mut result);
     return result;
 }
 
-fn permute(p: &mut [u8; 256]) {
-    let mut rng = rand::thread_rng();
+fn permute(rng: &mut Rng, p: &mut [u8; 256]) {
     for i in (1..256).rev() {
-        let target: usize = rng.gen_range(0, i + 1);
+        let target = (rng.rand64() * (i + 1) as f64) as usize;
         let tmp = p[i];
         p[i] = p[target];
         p[target] = tmp;
     }
 }
 
-fn perlin_generate() -> [Vec3<f64>; 256] {
-    let mut rng = rand::thread_rng();
+fn perlin_generate(rng: &mut Rng) -> [Vec3<f64>; 256] {
     let mut result = [Vec3::zero(); 256];
     for i in 0..256 {
-        let mut v = Vec3::new(-1. + 2. * rng.gen::<f64>(),
-                              -1. + 2. * rng.gen::<f64>(),
-                              -1. + 2. * rng.gen::<f64>());
+        let mut v = Vec3::new(-1. + 2. * rng.rand64(),
+                              -1. + 2. * rng.rand64(),
+                              -1. + 2. * rng.rand64());
         v.make_unit_vector();
         result[i] = v;
     }
     return result;
 }
 
-fn perlin_generate_float() -> [f64; 256] {
-    let mut rng = rand::thread_rng();
+fn perlin_generate_float(rng: &mut Rng) -> [f64; 256] {
     let mut result = [0.; 256];
     for i in 0..256 {
-        result[i] = rng.gen::<f64>();
+        result[i] = rng.rand64();
     }
     return result;
 }
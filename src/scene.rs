@@ -1,12 +1,182 @@
 use hitable::*;
+use hitable_list::*;
 use camera::*;
+use background::*;
+use integrator::*;
+use vec3::*;
 use output::*;
+use util::*;
+use std::thread;
 
 #[derive(Debug)]
 pub struct Scene {
     pub world: Box<Hitable>,
-    pub light_shapes: Box<Hitable>,
+    /// Objects to importance sample (see `SceneBuilder`).  These are shared
+    /// with `world`.
+    pub light_shapes: HitableList,
+    /// What rays that miss everything see.
+    pub background: Box<Background>,
+    /// How the light along each camera ray is worked out.
+    pub integrator: Box<Integrator>,
     pub camera: Camera,
+    /// If set, `camera` is replaced for every frame (see `set_frame`).
+    pub camera_animation: Option<CameraAnimation>,
     pub num_samples: u32, // TODO: put inside RenderQuality
     pub output_settings: OutputSettings,
+    pub render_settings: RenderSettings,
+}
+
+impl Scene {
+    /// Set up the camera and output filename for an animation frame.
+    pub fn set_frame(&mut self, frame: u32) {
+        if let Some(ref animation) = self.camera_animation {
+            self.camera = animation.camera(frame);
+        }
+        self.output_settings.template_values.frame = frame;
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RenderSettings {
+    /// Number of worker threads that render buckets.
+    pub num_threads: u32,
+    /// If set, render the whole image in passes of this many samples per
+    /// pixel, updating the output after each pass.
+    pub progressive_samples: Option<u32>,
+    /// If set, vary the number of samples per pixel based on how noisy the
+    /// pixel is.  This replaces `Scene::num_samples`.
+    pub adaptive: Option<AdaptiveSettings>,
+    /// Seed for all random numbers used while rendering.  Renders with the
+    /// same seed produce identical images.
+    pub seed: u64,
+    /// First and last frame to render (inclusive).
+    pub frames: (u32, u32),
+    /// Most bounces of each kind a path may take.
+    pub max_bounces: BounceLimits,
+    /// After this many bounces, paths are stopped at random (Russian
+    /// roulette) with a probability based on how much light they can still
+    /// carry.  The paths that survive are weighted up to make up for it.
+    pub roulette_depth: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct BounceLimits {
+    /// Bounces off diffuse surfaces and in participating media.
+    pub diffuse: u32,
+    /// Mirror-like reflections.
+    pub specular: u32,
+    /// Refraction through glass and other dielectrics.
+    pub transmission: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct AdaptiveSettings {
+    /// Every pixel gets at least this many samples.
+    pub min_samples: u32,
+    /// No pixel gets more than this many samples.
+    pub max_samples: u32,
+    /// A pixel stops sampling once the estimated standard error of its
+    /// luminance (relative to the luminance) drops below this.
+    pub noise_threshold: f64,
+}
+
+impl Default for RenderSettings {
+    fn default() -> RenderSettings {
+        let num_threads = thread::available_parallelism().map(|n| n.get() as u32).unwrap_or(1);
+        RenderSettings {
+            num_threads: num_threads,
+            progressive_samples: None,
+            adaptive: None,
+            seed: 0,
+            frames: (1, 1),
+            max_bounces: BounceLimits {
+                diffuse: 50,
+                specular: 50,
+                transmission: 50,
+            },
+            roulette_depth: 5,
+        }
+    }
+}
+
+/// Collects the objects for a scene.
+///
+/// Objects that emit light are also added to the scene's `light_shapes`, so
+/// that they are importance sampled.  The light list shares the objects with
+/// the world, so nothing has to be built twice.  For groups of objects (lists,
+/// BVHs, meshes) only the parts that emit are added.
+#[derive(Debug)]
+pub struct SceneBuilder {
+    world: HitableList,
+    lights: HitableList,
+    background: Box<Background>,
+    integrator: Box<Integrator>,
+}
+
+impl SceneBuilder {
+    pub fn new() -> SceneBuilder {
+        SceneBuilder {
+            world: HitableList::new(),
+            lights: HitableList::new(),
+            background: Box::new(ConstantBackground::new(Vec3::zero())),
+            integrator: Box::new(PathTracer::new(Heuristic::Power)),
+        }
+    }
+
+    pub fn add_hitable<T: Hitable + 'static>(&mut self, h: T) {
+        self.add_boxed(Box::new(h));
+    }
+
+    pub fn add_boxed(&mut self, h: Box<Hitable>) {
+        let sample = h.is_emissive();
+        self.add(h, sample);
+    }
+
+    /// Add an object that is importance sampled like a light, even though
+    /// it doesn't emit.  This helps with objects that focus light onto other
+    /// surfaces, such as glass spheres.
+    pub fn add_sampled(&mut self, h: Box<Hitable>) {
+        self.add(h, true);
+    }
+
+    fn add(&mut self, h: Box<Hitable>, sample: bool) {
+        if sample {
+            let h: Arc<Hitable> = Arc::from(h);
+            let mut lights = Vec::new();
+            if h.is_emissive() && h.add_lights(&mut lights) {
+                for light in lights {
+                    self.lights.add_shared(light);
+                }
+            } else {
+                self.lights.add_shared(h.clone());
+            }
+            self.world.add_shared(h);
+        } else {
+            self.world.add_boxed(h);
+        }
+    }
+
+    /// Defaults to black.
+    pub fn set_background(&mut self, background: Box<Background>) {
+        self.background = background;
+    }
+
+    /// Defaults to `PathTracer` with the power heuristic.
+    pub fn set_integrator(&mut self, integrator: Box<Integrator>) {
+        self.integrator = integrator;
+    }
+
+    pub fn build(self, camera: Camera, num_samples: u32, output_settings: OutputSettings) -> Scene {
+        Scene {
+            world: Box::new(self.world),
+            light_shapes: self.lights,
+            background: self.background,
+            integrator: self.integrator,
+            camera: camera,
+            camera_animation: None,
+            num_samples: num_samples,
+            output_settings: output_settings,
+            render_settings: RenderSettings::default(),
+        }
+    }
 }
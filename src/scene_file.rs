@@ -0,0 +1,1122 @@
+/*
+Scene description files.
+
+Scenes are written in TOML.  Textures and materials are named tables that
+objects refer to by name.  Colors are written as `[r, g, b]` arrays, and
+anywhere a texture is expected a color, the name of a texture, or an inline
+texture table may be used.
+
+    samples = 100
+
+    # Optional limits on how far paths go.  Each kind of bounce has its own
+    # limit, and after `roulette_depth` bounces dim paths are stopped at
+    # random.
+    [bounces]
+    diffuse = 8
+    specular = 16
+    transmission = 16
+    roulette_depth = 3
+
+    [output]
+    filename = "cornell.png"
+    width = 500
+    height = 500
+    # Optional display settings for 8-bit formats.
+    exposure = 0.0
+    tonemap = "aces"
+
+    [camera]
+    lookfrom = [278.0, 278.0, -800.0]
+    lookat = [278.0, 278.0, 0.0]
+    vfov = 40.0
+
+    # Animations list the camera's keyframes, and a `frames = [1, 48]`
+    # range at the top level.  Keys leave out whatever doesn't change.  The
+    # shutter (`time0` to `time1`) of each frame is `frame_duration` later
+    # than the frame before.
+    [[camera.keys]]
+    frame = 48
+    lookfrom = [278.0, 400.0, -800.0]
+
+    # What rays that miss everything see (black if left out).  The type is
+    # "constant" (with a `color`), "gradient" (a sky from `bottom` to `top`)
+    # or "environment" (an equirectangular Radiance .hdr `path`, turned by
+    # `rotation` degrees about the y axis).  A "sky" is a daylight sky and
+    # sun, set by `sun_direction` and `turbidity` (haze, 2 to 10).
+    # Environment maps and the sun are sampled like lights.
+    [background]
+    type = "gradient"
+
+    # How the image is rendered: "path" (the default), "normals" to show
+    # the surface normals, "ao" for ambient occlusion, counting objects
+    # within `distance` (anything, if left out), or "heatmap" to show how
+    # many box and object tests each camera ray takes (red at `scale`, 100
+    # if left out; set exposure 0 and tonemap "clamp" to see the colors
+    # unchanged).  Path tracing weighs light sampling against bounced
+    # rays with the "power" (default) or "balance" `heuristic`.
+    [integrator]
+    type = "ao"
+    distance = 100.0
+
+    [materials.white]
+    type = "lambertian"
+    albedo = [0.73, 0.73, 0.73]
+
+    [materials.light]
+    type = "diffuse_light"
+    emit = [15.0, 15.0, 15.0]
+
+    [[objects]]
+    type = "xz_rect"
+    x0 = 213.0
+    x1 = 343.0
+    z0 = 227.0
+    z1 = 332.0
+    k = 554.0
+    material = "light"
+
+    # Wrappers (flip_normals, translate, rotate_y, transform) take the
+    # wrapped object in an `object` sub-table.  A constant_medium (fog or
+    # smoke) takes its shape in a `boundary` sub-table, which doesn't need a
+    # material.
+    [[objects]]
+    type = "translate"
+    offset = [265.0, 0.0, 295.0]
+    [objects.object]
+    type = "box"
+    min = [0.0, 0.0, 0.0]
+    max = [165.0, 330.0, 165.0]
+    material = "white"
+
+    # Meshes can be loaded from Wavefront OBJ files (relative to the scene
+    # file).  Without a `material`, the materials come from the OBJ's MTL
+    # libraries.
+    [[objects]]
+    type = "obj"
+    path = "bunny.obj"
+
+    # Objects with an emissive material are sampled as lights
+    # automatically.  Other objects that focus light (such as glass) can be
+    # sampled too, which reduces noise in the caustics.
+    [[objects]]
+    type = "sphere"
+    center = [190.0, 90.0, 190.0]
+    radius = 90.0
+    material = "glass"
+    sample_as_light = true
+
+See `scenes/cornell_box.toml` for a complete example.
+*/
+
+use vec3::*;
+use matrix4::*;
+use hitable::*;
+use hitable_list::*;
+use sphere::*;
+use moving_sphere::*;
+use aarect::*;
+use hbox::*;
+use triangle::*;
+use constant_medium::*;
+use obj::*;
+use bvh::*;
+use material::*;
+use texture::*;
+use camera::*;
+use background::*;
+use sky::*;
+use integrator::*;
+use scene::*;
+use output::{OutputSettings, OutputFormat, DisplaySettings, ToneMap, TemplateValues};
+use util::*;
+use image;
+use toml;
+use serde::de::DeserializeOwned;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error as std_Error;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// An error loading a scene file.
+#[derive(Debug)]
+pub struct SceneError {
+    /// Line (1-based) in the scene file where the problem is, if known.
+    line: Option<usize>,
+    message: String,
+}
+
+impl SceneError {
+    fn new<S: Into<String>>(message: S) -> SceneError {
+        SceneError {
+            line: None,
+            message: message.into(),
+        }
+    }
+
+    /// Set the line number if it isn't already known.
+    fn or_line(mut self, line: Option<usize>) -> SceneError {
+        if self.line.is_none() {
+            self.line = line;
+        }
+        self
+    }
+
+    pub fn line(&self) -> Option<usize> {
+        self.line
+    }
+}
+
+impl std_Error for SceneError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(fmt, "line {}: {}", line, self.message),
+            None => write!(fmt, "{}", self.message),
+        }
+    }
+}
+
+impl From<io::Error> for SceneError {
+    fn from(e: io::Error) -> SceneError {
+        SceneError::new(e.to_string())
+    }
+}
+
+impl From<toml::de::Error> for SceneError {
+    fn from(e: toml::de::Error) -> SceneError {
+        // The toml error message already includes the line.
+        SceneError::new(e.to_string())
+    }
+}
+
+/// The top level of the file.
+///
+/// Textures, materials and objects are kept as raw values and deserialized
+/// one at a time, so that errors inside of them can be tied to a line.
+/// (The toml crate loses track of the position inside tagged enums.)
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SceneDesc {
+    #[serde(default = "default_samples")]
+    samples: u32,
+    /// First and last frame of an animation.
+    #[serde(default = "default_frames")]
+    frames: [u32; 2],
+    bounces: Option<BouncesDesc>,
+    output: OutputDesc,
+    camera: CameraDesc,
+    background: Option<toml::Value>,
+    integrator: Option<toml::Value>,
+    #[serde(default)]
+    textures: BTreeMap<String, toml::Value>,
+    #[serde(default)]
+    materials: BTreeMap<String, toml::Value>,
+    #[serde(default)]
+    objects: Vec<toml::Value>,
+}
+
+fn default_samples() -> u32 { 100 }
+fn default_frames() -> [u32; 2] { [1, 1] }
+
+/// Anything left out keeps the default from `RenderSettings`.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BouncesDesc {
+    diffuse: Option<u32>,
+    specular: Option<u32>,
+    transmission: Option<u32>,
+    roulette_depth: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct OutputDesc {
+    #[serde(default = "default_filename")]
+    filename: String,
+    width: u32,
+    height: u32,
+    /// Exposure adjustment in stops.
+    #[serde(default)]
+    exposure: f64,
+    /// Tone mapping operator: "clamp", "reinhard" or "aces".
+    tonemap: Option<String>,
+}
+
+fn default_filename() -> String { String::from("output.png") }
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraDesc {
+    lookfrom: [f64; 3],
+    lookat: [f64; 3],
+    #[serde(default = "default_vup")]
+    vup: [f64; 3],
+    vfov: f64,
+    #[serde(default)]
+    aperture: f64,
+    /// Defaults to the distance between lookfrom and lookat.
+    focus_dist: Option<f64>,
+    #[serde(default)]
+    time0: f64,
+    #[serde(default = "default_time1")]
+    time1: f64,
+    /// Scene time from one frame to the next.
+    #[serde(default = "default_frame_duration")]
+    frame_duration: f64,
+    #[serde(default)]
+    keys: Vec<CameraKeyDesc>,
+}
+
+fn default_vup() -> [f64; 3] { [0., 1., 0.] }
+fn default_time1() -> f64 { 1. }
+fn default_frame_duration() -> f64 { 1. }
+
+/// Camera keyframe.  Anything left out is the same as the key before it
+/// (or the `[camera]` table for the first key).
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CameraKeyDesc {
+    frame: u32,
+    lookfrom: Option<[f64; 3]>,
+    lookat: Option<[f64; 3]>,
+    vfov: Option<f64>,
+    aperture: Option<f64>,
+    focus_dist: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum BackgroundDesc {
+    Constant { color: [f64; 3] },
+    Gradient {
+        #[serde(default = "default_sky_bottom")]
+        bottom: [f64; 3],
+        #[serde(default = "default_sky_top")]
+        top: [f64; 3],
+    },
+    Environment {
+        path: String,
+        #[serde(default)]
+        rotation: f64,
+    },
+    Sky {
+        sun_direction: [f64; 3],
+        #[serde(default = "default_turbidity")]
+        turbidity: f64,
+        #[serde(default = "default_sun_size")]
+        sun_size: f64,
+        #[serde(default = "default_sun_intensity")]
+        sun_intensity: f64,
+        #[serde(default = "default_intensity")]
+        intensity: f64,
+    },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum IntegratorDesc {
+    /// `heuristic` is "balance" or "power".
+    Path { heuristic: Option<String> },
+    Normals,
+    Ao { distance: Option<f64> },
+    Heatmap { scale: Option<f64> },
+}
+
+fn default_sky_bottom() -> [f64; 3] { [1.0, 1.0, 1.0] }
+fn default_sky_top() -> [f64; 3] { [0.5, 0.7, 1.0] }
+fn default_turbidity() -> f64 { 3. }
+fn default_sun_size() -> f64 { 0.53 }
+fn default_sun_intensity() -> f64 { 6. }
+fn default_intensity() -> f64 { 1. }
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureDesc {
+    Constant { color: [f64; 3] },
+    Checker { odd: TextureRef, even: TextureRef },
+    Noise { scale: f64 },
+    Image { path: String },
+}
+
+/// A place where a texture is expected.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+enum TextureRef {
+    Color([f64; 3]),
+    Named(String),
+    Inline(Box<TextureDesc>),
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum MaterialDesc {
+    Lambertian { albedo: TextureRef },
+    Metal {
+        albedo: [f64; 3],
+        #[serde(default)]
+        fuzz: f64,
+    },
+    Dielectric { ref_idx: f64 },
+    DiffuseLight { emit: TextureRef },
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+enum HitableDesc {
+    Sphere {
+        center: [f64; 3],
+        radius: f64,
+        material: Option<String>,
+    },
+    MovingSphere {
+        center0: [f64; 3],
+        center1: [f64; 3],
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        material: Option<String>,
+    },
+    XyRect { x0: f64, x1: f64, y0: f64, y1: f64, k: f64, material: Option<String> },
+    XzRect { x0: f64, x1: f64, z0: f64, z1: f64, k: f64, material: Option<String> },
+    YzRect { y0: f64, y1: f64, z0: f64, z1: f64, k: f64, material: Option<String> },
+    Triangle {
+        vertices: [[f64; 3]; 3],
+        normals: Option<[[f64; 3]; 3]>,
+        uvs: Option<[[f64; 2]; 3]>,
+        material: Option<String>,
+    },
+    Mesh {
+        positions: Vec<[f64; 3]>,
+        #[serde(default)]
+        normals: Vec<[f64; 3]>,
+        #[serde(default)]
+        uvs: Vec<[f64; 2]>,
+        triangles: Vec<[usize; 3]>,
+        material: Option<String>,
+    },
+    /// A Wavefront OBJ file.  Without a material, the materials come from
+    /// the file's MTL libraries.
+    Obj {
+        path: String,
+        material: Option<String>,
+    },
+    #[serde(rename = "box")]
+    HBox {
+        min: [f64; 3],
+        max: [f64; 3],
+        material: Option<String>,
+    },
+    FlipNormals { object: Box<HitableDesc> },
+    Translate {
+        offset: [f64; 3],
+        object: Box<HitableDesc>,
+    },
+    RotateY {
+        angle: f64,
+        object: Box<HitableDesc>,
+    },
+    /// The transforms are applied in order.
+    Transform {
+        transforms: Vec<TransformDesc>,
+        object: Box<HitableDesc>,
+    },
+    /// Fog or smoke filling a convex `boundary` object.
+    ConstantMedium {
+        density: f64,
+        albedo: TextureRef,
+        boundary: Box<HitableDesc>,
+    },
+    List { objects: Vec<HitableDesc> },
+    Bvh { objects: Vec<HitableDesc> },
+}
+
+/// One step of a `transform` object, written as a single-key inline table
+/// such as `{ rotate_x = 45.0 }`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum TransformDesc {
+    Translate([f64; 3]),
+    Scale(ScaleDesc),
+    RotateX(f64),
+    RotateY(f64),
+    RotateZ(f64),
+    Rotate { axis: [f64; 3], angle: f64 },
+    /// Row-major 4x4 matrix.
+    Matrix([[f64; 4]; 4]),
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum ScaleDesc {
+    Uniform(f64),
+    Axes([f64; 3]),
+}
+
+impl TransformDesc {
+    fn matrix(&self) -> Matrix4 {
+        match *self {
+            TransformDesc::Translate(ref offset) => Matrix4::translation(&vec3(offset)),
+            TransformDesc::Scale(ScaleDesc::Uniform(s)) => Matrix4::scale(&Vec3::new(s, s, s)),
+            TransformDesc::Scale(ScaleDesc::Axes(ref s)) => Matrix4::scale(&vec3(s)),
+            TransformDesc::RotateX(angle) => Matrix4::rotation_x(angle),
+            TransformDesc::RotateY(angle) => Matrix4::rotation_y(angle),
+            TransformDesc::RotateZ(angle) => Matrix4::rotation_z(angle),
+            TransformDesc::Rotate { ref axis, angle } => Matrix4::rotation(&vec3(axis), angle),
+            TransformDesc::Matrix(ref m) => Matrix4::new(*m),
+        }
+    }
+}
+
+fn vec3(a: &[f64; 3]) -> Vec3<f64> {
+    Vec3::new(a[0], a[1], a[2])
+}
+
+fn uv(a: &[f64; 2]) -> (f64, f64) {
+    (a[0], a[1])
+}
+
+/// Load a scene from a scene description file.
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<Scene, SceneError> {
+    load_scene_frames(path, None)
+}
+
+/// Load a scene file, replacing the frame range given in the file.
+///
+/// The frame range has to be known while loading, so that the bounding
+/// boxes of moving objects cover every frame.
+pub fn load_scene_frames<P: AsRef<Path>>(path: P, frames: Option<(u32, u32)>) -> Result<Scene, SceneError> {
+    let path = path.as_ref();
+    let mut source = String::new();
+    File::open(path)?.read_to_string(&mut source)?;
+    let base_dir = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+    let mut scene = parse_scene_frames(&source, &base_dir, frames)?;
+    if let Some(stem) = path.file_stem() {
+        scene.output_settings.template_values.scene = stem.to_string_lossy().into_owned();
+    }
+    Ok(scene)
+}
+
+/// Build a scene from the text of a scene description.
+///
+/// Relative paths (such as image textures) are relative to `base_dir`.
+pub fn parse_scene(source: &str, base_dir: &Path) -> Result<Scene, SceneError> {
+    parse_scene_frames(source, base_dir, None)
+}
+
+/// Build a scene from the text of a scene description, replacing the frame
+/// range given in the description.
+pub fn parse_scene_frames(source: &str, base_dir: &Path, frames: Option<(u32, u32)>) -> Result<Scene, SceneError> {
+    let desc: SceneDesc = toml::from_str(source)?;
+    let frames = frames.unwrap_or((desc.frames[0], desc.frames[1]));
+    if frames.0 > frames.1 {
+        return Err(SceneError::new(format!("frames {}..{} are out of order", frames.0, frames.1)));
+    }
+    let mut builder = Builder {
+        source: source,
+        base_dir: base_dir.to_path_buf(),
+        time0: desc.camera.time0,
+        time1: desc.camera.time1,
+        texture_descs: BTreeMap::new(),
+        material_descs: BTreeMap::new(),
+        materials: HashMap::new(),
+        images: HashMap::new(),
+        placeholder: Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::zero())))),
+    };
+
+    let mut output = OutputSettings {
+        format: OutputFormat::Png,
+        filename_template: desc.output.filename.clone(),
+        width: desc.output.width,
+        height: desc.output.height,
+        display: DisplaySettings {
+            exposure: desc.output.exposure,
+            tone_map: ToneMap::Clamp,
+        },
+        template_values: TemplateValues::default(),
+    };
+    if let Err(e) = output.set_filename_template(desc.output.filename.clone()) {
+        return Err(SceneError::new(format!("output filename `{}`: {}", desc.output.filename, e))
+                   .or_line(builder.table_line("output")));
+    }
+    if let Some(ref tonemap) = desc.output.tonemap {
+        output.display.tone_map = tonemap.parse()
+            .map_err(|e| SceneError::new(format!("output tonemap `{}`: {}", tonemap, e))
+                     .or_line(builder.table_line("output")))?;
+    }
+
+    let animation = builder.camera(&desc.camera, &output, frames.0)
+        .map_err(|e| e.or_line(builder.table_line("camera")))?;
+    // Moving objects have to be bounded over the shutter of every frame.
+    let (first0, first1) = animation.shutter(frames.0);
+    let (last0, last1) = animation.shutter(frames.1);
+    builder.time0 = first0.min(last0);
+    builder.time1 = first1.max(last1);
+    let camera = animation.camera(frames.0);
+
+    for (name, value) in &desc.textures {
+        let line = builder.table_line(&format!("textures.{}", name));
+        let t = parse_item(value, &format!("textures.{}", name), line)?;
+        builder.texture_descs.insert(name.clone(), t);
+    }
+    for (name, value) in &desc.materials {
+        let line = builder.table_line(&format!("materials.{}", name));
+        let m = parse_item(value, &format!("materials.{}", name), line)?;
+        builder.material_descs.insert(name.clone(), m);
+    }
+    for name in desc.materials.keys() {
+        builder.material(name)?;
+    }
+
+    let mut world = SceneBuilder::new();
+    if let Some(ref value) = desc.background {
+        let line = builder.table_line("background");
+        let background = parse_item(value, "background", line)?;
+        world.set_background(builder.background(&background).map_err(|e| e.or_line(line))?);
+    }
+    if let Some(ref value) = desc.integrator {
+        let line = builder.table_line("integrator");
+        let integrator: IntegratorDesc = parse_item(value, "integrator", line)?;
+        world.set_integrator(match integrator {
+            IntegratorDesc::Path { ref heuristic } => {
+                let heuristic = match *heuristic {
+                    Some(ref h) => h.parse().map_err(|e| SceneError::new(format!("integrator: {}", e)).or_line(line))?,
+                    None => Heuristic::Power,
+                };
+                Box::new(PathTracer::new(heuristic))
+            },
+            IntegratorDesc::Normals => Box::new(NormalView::new()),
+            IntegratorDesc::Ao { distance } => Box::new(AmbientOcclusion::new(distance.unwrap_or(f64::MAX))),
+            IntegratorDesc::Heatmap { scale } => {
+                let scale = scale.unwrap_or(DEFAULT_HEATMAP_SCALE);
+                if !(scale > 0.) {
+                    return Err(SceneError::new("integrator: scale must be positive").or_line(line));
+                }
+                Box::new(Heatmap::new(scale))
+            },
+        });
+    }
+    for (i, value) in desc.objects.iter().enumerate() {
+        let path = format!("objects[{}]", i);
+        let line = builder.array_line("objects", i);
+        // `sample_as_light` is allowed on any top-level object, so take it
+        // out before parsing the object itself.
+        let mut value = value.clone();
+        let sample = match value.as_table_mut().and_then(|t| t.remove("sample_as_light")) {
+            Some(toml::Value::Boolean(b)) => b,
+            Some(_) => {
+                return Err(SceneError::new(format!("{}: `sample_as_light` must be true or false", path))
+                           .or_line(line));
+            },
+            None => false,
+        };
+        let object = parse_item(&value, &path, line)?;
+        let h = builder.hitable(&object, &path, true).map_err(|e| e.or_line(line))?;
+        if sample {
+            world.add_sampled(h);
+        } else {
+            world.add_boxed(h);
+        }
+    }
+
+    let mut scene = world.build(camera, desc.samples, output);
+    scene.camera_animation = Some(animation);
+    scene.render_settings.frames = frames;
+    scene.output_settings.template_values.frame = frames.0;
+    if let Some(ref bounces) = desc.bounces {
+        let settings = &mut scene.render_settings;
+        settings.max_bounces.diffuse = bounces.diffuse.unwrap_or(settings.max_bounces.diffuse);
+        settings.max_bounces.specular = bounces.specular.unwrap_or(settings.max_bounces.specular);
+        settings.max_bounces.transmission = bounces.transmission.unwrap_or(settings.max_bounces.transmission);
+        settings.roulette_depth = bounces.roulette_depth.unwrap_or(settings.roulette_depth);
+    }
+    Ok(scene)
+}
+
+/// Deserialize a single texture, material or object.  `what` and `line`
+/// describe where it is for error messages.
+fn parse_item<T: DeserializeOwned>(value: &toml::Value, what: &str, line: Option<usize>) -> Result<T, SceneError> {
+    value.clone().try_into().map_err(|e| {
+        SceneError::new(format!("{}: {}", what, e)).or_line(line)
+    })
+}
+
+/// Converts the deserialized description into the actual scene objects.
+struct Builder<'a> {
+    source: &'a str,
+    base_dir: PathBuf,
+    /// Time range of the camera shutter over all frames (for building
+    /// BVHs).
+    time0: f64,
+    time1: f64,
+    texture_descs: BTreeMap<String, TextureDesc>,
+    material_descs: BTreeMap<String, MaterialDesc>,
+    /// Materials that have been built so far (by name).
+    materials: HashMap<String, Arc<Material>>,
+    /// Images that have been loaded so far (by path).
+    images: HashMap<PathBuf, image::DynamicImage>,
+    /// Material used for objects that are only used for their shape (such
+    /// as the boundary of a constant_medium), so it doesn't matter what it
+    /// is.
+    placeholder: Arc<Material>,
+}
+
+impl<'a> Builder<'a> {
+    /// Line of the header of the table `[name]`.
+    fn table_line(&self, name: &str) -> Option<usize> {
+        let header = format!("[{}]", name);
+        self.source.lines()
+            .position(|line| line.trim() == header)
+            .map(|i| i + 1)
+    }
+
+    /// Line of the header of the `index`th element of the array of tables
+    /// `[[name]]`.
+    fn array_line(&self, name: &str, index: usize) -> Option<usize> {
+        let header = format!("[[{}]]", name);
+        self.source.lines()
+            .enumerate()
+            .filter(|&(_, line)| line.trim() == header)
+            .nth(index)
+            .map(|(i, _)| i + 1)
+    }
+
+    /// `first_frame` is the frame that is exposed from `time0` to `time1`.
+    fn camera(&self, desc: &CameraDesc, output: &OutputSettings, first_frame: u32) -> Result<CameraAnimation, SceneError> {
+        if output.height == 0 {
+            return Err(SceneError::new("output height must not be zero"));
+        }
+        let mut descs: Vec<&CameraKeyDesc> = desc.keys.iter().collect();
+        descs.sort_by_key(|k| k.frame);
+        for pair in descs.windows(2) {
+            if pair[0].frame == pair[1].frame {
+                return Err(SceneError::new(format!("camera: more than one key for frame {}", pair[0].frame)));
+            }
+        }
+        let mut lookfrom = desc.lookfrom;
+        let mut lookat = desc.lookat;
+        let mut vfov = desc.vfov;
+        let mut aperture = desc.aperture;
+        // Defaults to the distance between lookfrom and lookat.
+        let mut focus_dist = desc.focus_dist;
+        let key = |frame, lookfrom: &[f64; 3], lookat: &[f64; 3], vfov, aperture, focus_dist: Option<f64>| {
+            let lookfrom = vec3(lookfrom);
+            let lookat = vec3(lookat);
+            CameraKey {
+                frame: frame,
+                lookfrom: lookfrom,
+                lookat: lookat,
+                vfov: vfov,
+                aperture: aperture,
+                focus_dist: focus_dist.unwrap_or((lookfrom - lookat).length()),
+            }
+        };
+        let mut keys = Vec::new();
+        for k in descs {
+            lookfrom = k.lookfrom.unwrap_or(lookfrom);
+            lookat = k.lookat.unwrap_or(lookat);
+            vfov = k.vfov.unwrap_or(vfov);
+            aperture = k.aperture.unwrap_or(aperture);
+            focus_dist = k.focus_dist.or(focus_dist);
+            keys.push(key(k.frame, &lookfrom, &lookat, vfov, aperture, focus_dist));
+        }
+        if keys.is_empty() {
+            keys.push(key(first_frame, &lookfrom, &lookat, vfov, aperture, focus_dist));
+        }
+        Ok(CameraAnimation::new(keys,
+                                vec3(&desc.vup),
+                                output.width as f64 / output.height as f64,
+                                first_frame,
+                                desc.time0,
+                                desc.time1,
+                                desc.frame_duration))
+    }
+
+    fn background(&self, desc: &BackgroundDesc) -> Result<Box<Background>, SceneError> {
+        Ok(match *desc {
+            BackgroundDesc::Constant { ref color } => Box::new(ConstantBackground::new(vec3(color))),
+            BackgroundDesc::Gradient { ref bottom, ref top } => {
+                Box::new(GradientBackground::new(vec3(bottom), vec3(top)))
+            },
+            BackgroundDesc::Environment { ref path, rotation } => {
+                let full_path = self.base_dir.join(path);
+                let map = EnvironmentMap::load(&full_path, rotation).map_err(|e| {
+                    SceneError::new(format!("background: could not load environment map `{}`: {}",
+                                            full_path.display(), e))
+                })?;
+                Box::new(map)
+            },
+            BackgroundDesc::Sky { ref sun_direction, turbidity, sun_size, sun_intensity, intensity } => {
+                if vec3(sun_direction).length() == 0. {
+                    return Err(SceneError::new("background: sun_direction must not be zero"));
+                }
+                Box::new(Sky::new(vec3(sun_direction), turbidity, sun_size, sun_intensity, intensity))
+            },
+        })
+    }
+
+    fn image(&mut self, path: &str) -> Result<image::DynamicImage, SceneError> {
+        let full_path = self.base_dir.join(path);
+        if !self.images.contains_key(&full_path) {
+            let img = image::open(&full_path).map_err(|e| {
+                SceneError::new(format!("could not load image `{}`: {}", full_path.display(), e))
+            })?;
+            self.images.insert(full_path.clone(), img);
+        }
+        Ok(self.images[&full_path].clone())
+    }
+
+    /// Build a texture.  `stack` is the list of named textures currently
+    /// being built, to catch textures that refer to themselves.
+    fn texture(&mut self, r: &TextureRef, stack: &mut Vec<String>) -> Result<Box<Texture>, SceneError> {
+        match *r {
+            TextureRef::Color(ref c) => Ok(Box::new(ConstantTexture::new(vec3(c)))),
+            TextureRef::Inline(ref desc) => self.texture_from_desc(desc, stack),
+            TextureRef::Named(ref name) => {
+                let tdesc = match self.texture_descs.get(name) {
+                    Some(tdesc) => tdesc.clone(),
+                    None => return Err(SceneError::new(format!("unknown texture `{}`", name))),
+                };
+                if stack.contains(name) {
+                    return Err(SceneError::new(format!("texture `{}` refers to itself", name)));
+                }
+                stack.push(name.clone());
+                let t = self.texture_from_desc(&tdesc, stack)
+                    .map_err(|e| e.or_line(self.table_line(&format!("textures.{}", name))))?;
+                stack.pop();
+                Ok(t)
+            }
+        }
+    }
+
+    fn texture_from_desc(&mut self, desc: &TextureDesc, stack: &mut Vec<String>) -> Result<Box<Texture>, SceneError> {
+        Ok(match *desc {
+            TextureDesc::Constant { ref color } => Box::new(ConstantTexture::new(vec3(color))),
+            TextureDesc::Checker { ref odd, ref even } => {
+                let odd = self.texture(odd, stack)?;
+                let even = self.texture(even, stack)?;
+                Box::new(CheckerTexture::new(odd, even))
+            },
+            TextureDesc::Noise { scale } => Box::new(NoiseTexture::new(scale)),
+            TextureDesc::Image { ref path } => Box::new(ImageTexture::new(self.image(path)?)),
+        })
+    }
+
+    fn material(&mut self, name: &str) -> Result<Arc<Material>, SceneError> {
+        if let Some(m) = self.materials.get(name) {
+            return Ok(m.clone());
+        }
+        let mdesc = match self.material_descs.get(name) {
+            Some(mdesc) => mdesc.clone(),
+            None => return Err(SceneError::new(format!("unknown material `{}`", name))),
+        };
+        let mut stack = Vec::new();
+        let m: Result<Arc<Material>, SceneError> = match mdesc {
+            MaterialDesc::Lambertian { ref albedo } => {
+                self.texture(albedo, &mut stack).map(|t| Arc::new(Lambertian::new(t)) as Arc<Material>)
+            },
+            MaterialDesc::Metal { ref albedo, fuzz } => Ok(Arc::new(Metal::new(vec3(albedo), fuzz))),
+            MaterialDesc::Dielectric { ref_idx } => Ok(Arc::new(Dielectric::new(ref_idx))),
+            MaterialDesc::DiffuseLight { ref emit } => {
+                self.texture(emit, &mut stack).map(|t| Arc::new(DiffuseLight::new(t)) as Arc<Material>)
+            },
+        };
+        let m = m.map_err(|e| e.or_line(self.table_line(&format!("materials.{}", name))))?;
+        self.materials.insert(name.to_string(), m.clone());
+        Ok(m)
+    }
+
+    fn object_material(&mut self, name: &Option<String>, path: &str, required: bool) -> Result<Arc<Material>, SceneError> {
+        match *name {
+            Some(ref name) => self.material(name).map_err(|e| {
+                SceneError::new(format!("{}: {}", path, e.message))
+            }),
+            None if required => Err(SceneError::new(format!("{}: missing `material`", path))),
+            None => Ok(self.placeholder.clone()),
+        }
+    }
+
+    /// Build an object.  `path` describes where the object is in the file
+    /// for error messages.  `need_material` is false for objects that
+    /// are only used for their shape.
+    fn hitable(&mut self, desc: &HitableDesc, path: &str, need_material: bool) -> Result<Box<Hitable>, SceneError> {
+        Ok(match *desc {
+            HitableDesc::Sphere { ref center, radius, ref material } => {
+                let m = self.object_material(material, path, need_material)?;
+                Box::new(Sphere::new(vec3(center), radius, m))
+            },
+            HitableDesc::MovingSphere { ref center0, ref center1, time0, time1, radius, ref material } => {
+                let m = self.object_material(material, path, need_material)?;
+                Box::new(MovingSphere::new(vec3(center0), vec3(center1), time0, time1, radius, m))
+            },
+            HitableDesc::XyRect { x0, x1, y0, y1, k, ref material } => {
+                let m = self.object_material(material, path, need_material)?;
+                Box::new(XYRect::new(x0, x1, y0, y1, k, m))
+            },
+            HitableDesc::XzRect { x0, x1, z0, z1, k, ref material } => {
+                let m = self.object_material(material, path, need_material)?;
+                Box::new(XZRect::new(x0, x1, z0, z1, k, m))
+            },
+            HitableDesc::YzRect { y0, y1, z0, z1, k, ref material } => {
+                let m = self.object_material(material, path, need_material)?;
+                Box::new(YZRect::new(y0, y1, z0, z1, k, m))
+            },
+            HitableDesc::Triangle { ref vertices, ref normals, ref uvs, ref material } => {
+                let m = self.object_material(material, path, need_material)?;
+                let vertices = [vec3(&vertices[0]), vec3(&vertices[1]), vec3(&vertices[2])];
+                let normals = normals.as_ref().map(|n| [vec3(&n[0]), vec3(&n[1]), vec3(&n[2])]);
+                let uvs = uvs.as_ref().map(|t| [uv(&t[0]), uv(&t[1]), uv(&t[2])]);
+                Box::new(Triangle::new_with_attributes(vertices, normals, uvs, m))
+            },
+            HitableDesc::Mesh { ref positions, ref normals, ref uvs, ref triangles, ref material } => {
+                let m = self.object_material(material, path, need_material)?;
+                if triangles.is_empty() {
+                    return Err(SceneError::new(format!("{}: mesh has no triangles", path)));
+                }
+                if !normals.is_empty() && normals.len() != positions.len() {
+                    return Err(SceneError::new(format!("{}: mesh must have one normal per position", path)));
+                }
+                if !uvs.is_empty() && uvs.len() != positions.len() {
+                    return Err(SceneError::new(format!("{}: mesh must have one uv per position", path)));
+                }
+                if let Some(i) = triangles.iter().position(|tri| tri.iter().any(|&v| v >= positions.len())) {
+                    return Err(SceneError::new(format!("{}: triangles[{}] has an index out of range", path, i)));
+                }
+                Box::new(TriangleMesh::new(positions.iter().map(vec3).collect(),
+                                           normals.iter().map(vec3).collect(),
+                                           uvs.iter().map(uv).collect(),
+                                           triangles.clone(),
+                                           m))
+            },
+            HitableDesc::Obj { path: ref file, ref material } => {
+                let m = match *material {
+                    Some(_) => Some(self.object_material(material, path, need_material)?),
+                    // Shapes don't need the MTL materials.
+                    None if !need_material => Some(self.placeholder.clone()),
+                    None => None,
+                };
+                load_obj(self.base_dir.join(file), m).map_err(|e| {
+                    SceneError::new(format!("{}: {}", path, e))
+                })?
+            },
+            HitableDesc::HBox { ref min, ref max, ref material } => {
+                let m = self.object_material(material, path, need_material)?;
+                Box::new(HBox::new(vec3(min), vec3(max), m))
+            },
+            HitableDesc::FlipNormals { ref object } => {
+                let h = self.hitable(object, &format!("{}.object", path), need_material)?;
+                Box::new(FlipNormals::new(h))
+            },
+            HitableDesc::Translate { ref offset, ref object } => {
+                let h = self.hitable(object, &format!("{}.object", path), need_material)?;
+                Box::new(Translate::new(h, vec3(offset)))
+            },
+            HitableDesc::RotateY { angle, ref object } => {
+                let h = self.hitable(object, &format!("{}.object", path), need_material)?;
+                Box::new(RotateY::new(h, angle))
+            },
+            HitableDesc::Transform { ref transforms, ref object } => {
+                let h = self.hitable(object, &format!("{}.object", path), need_material)?;
+                let matrix = transforms.iter().fold(Matrix4::identity(), |m, t| t.matrix() * m);
+                if matrix.inverse().is_none() {
+                    return Err(SceneError::new(format!("{}: transform is not invertible", path)));
+                }
+                Box::new(Transform::new(h, matrix))
+            },
+            HitableDesc::ConstantMedium { density, ref albedo, ref boundary } => {
+                // The boundary is only used for its shape.
+                let h = self.hitable(boundary, &format!("{}.boundary", path), false)?;
+                let t = self.texture(albedo, &mut Vec::new()).map_err(|e| {
+                    SceneError::new(format!("{}: {}", path, e.message))
+                })?;
+                Box::new(ConstantMedium::new(h, density, t))
+            },
+            HitableDesc::List { ref objects } => {
+                let mut list = HitableList::new();
+                for (i, object) in objects.iter().enumerate() {
+                    list.add_boxed(self.hitable(object, &format!("{}.objects[{}]", path, i), need_material)?);
+                }
+                Box::new(list)
+            },
+            HitableDesc::Bvh { ref objects } => {
+                if objects.is_empty() {
+                    return Err(SceneError::new(format!("{}: bvh must have at least one object", path)));
+                }
+                let mut list = Vec::with_capacity(objects.len());
+                for (i, object) in objects.iter().enumerate() {
+                    let h = self.hitable(object, &format!("{}.objects[{}]", path, i), need_material)?;
+                    if h.bounding_box(self.time0, self.time1).is_none() {
+                        return Err(SceneError::new(format!("{}.objects[{}]: objects in a bvh must have a bounding box", path, i)));
+                    }
+                    list.push(h);
+                }
+                Box::new(BVH::new(list, self.time0, self.time1))
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cornell_box() {
+        let scene = parse_scene(include_str!("../scenes/cornell_box.toml"), Path::new("scenes")).unwrap();
+        assert_eq!(scene.num_samples, 100);
+        assert_eq!(scene.output_settings.width, 500);
+        assert!(scene.world.bounding_box(0., 1.).is_some());
+        // The light, plus the glass sphere.
+        assert_eq!(scene.light_shapes.len(), 2);
+    }
+
+    #[test]
+    fn test_errors() {
+        let source = "
+[output]
+width = 10
+height = 10
+
+[camera]
+lookfrom = [0.0, 0.0, 0.0]
+lookat = [0.0, 0.0, -1.0]
+vfov = 90.0
+
+[[objects]]
+type = \"sphere\"
+center = [0.0, 0.0, -1.0]
+radius = 0.5
+material = \"nope\"
+";
+        let e = parse_scene(source, Path::new(".")).unwrap_err();
+        assert_eq!(e.line(), Some(11));
+        assert_eq!(e.to_string(), "line 11: objects[0]: unknown material `nope`");
+
+        let e = parse_scene(&source.replace("radius = 0.5\nmaterial", "radius = \"big\"\nmaterial"),
+                            Path::new(".")).unwrap_err();
+        assert_eq!(e.to_string(), "line 11: objects[0]: invalid type: string \"big\", expected f64");
+
+        let e = parse_scene(&source.replace("height = 10\n", "height = 10\ntonemap = \"filmic\"\n"),
+                            Path::new(".")).unwrap_err();
+        assert_eq!(e.to_string(),
+                   "line 2: output tonemap `filmic`: Output Error: Unsupported tone mapping operator");
+
+    }
+
+    #[test]
+    fn test_transform() {
+        let source = "
+[output]
+width = 10
+height = 10
+
+[camera]
+lookfrom = [0.0, 0.0, 0.0]
+lookat = [0.0, 0.0, -1.0]
+vfov = 90.0
+
+[materials.white]
+type = \"lambertian\"
+albedo = [0.5, 0.5, 0.5]
+
+[[objects]]
+type = \"transform\"
+transforms = [{ scale = [1.0, 2.0, 1.0] }, { rotate_z = 90.0 }, { translate = [0.0, 0.0, -5.0] }]
+[objects.object]
+type = \"box\"
+min = [0.0, 0.0, 0.0]
+max = [1.0, 1.0, 1.0]
+material = \"white\"
+";
+        let scene = parse_scene(source, Path::new(".")).unwrap();
+        let bbox = scene.world.bounding_box(0., 1.).unwrap();
+        assert!((bbox.min() - Vec3::new(-2.0, 0.0, -5.0)).length() < 1e-9);
+        assert!((bbox.max() - Vec3::new(0.0, 1.0, -4.0)).length() < 1e-9);
+
+        let e = parse_scene(&source.replace("scale = [1.0, 2.0, 1.0]", "scale = 0.0"),
+                            Path::new(".")).unwrap_err();
+        assert_eq!(e.to_string(), "line 15: objects[0]: transform is not invertible");
+        let e = parse_scene(&source.replace("scale = [1.0, 2.0, 1.0]", "scale = [1.0, nan, 1.0]"),
+                            Path::new(".")).unwrap_err();
+        assert_eq!(e.to_string(), "line 15: objects[0]: transform is not invertible");
+
+        let scene = parse_scene(&format!("[bounces]\ndiffuse = 4\nroulette_depth = 2\n{}", source),
+                                Path::new(".")).unwrap();
+        assert_eq!(scene.render_settings.max_bounces.diffuse, 4);
+        assert_eq!(scene.render_settings.max_bounces.specular, 50);
+        assert_eq!(scene.render_settings.roulette_depth, 2);
+
+        let e = parse_scene(&format!("[integrator]\ntype = \"whitted\"\n{}", source),
+                            Path::new(".")).unwrap_err();
+        assert!(e.to_string().starts_with("line 1: integrator: unknown variant `whitted`"), "{}", e);
+        let e = parse_scene(&format!("[integrator]\ntype = \"heatmap\"\nscale = 0.0\n{}", source),
+                            Path::new(".")).unwrap_err();
+        assert_eq!(e.to_string(), "line 1: integrator: scale must be positive");
+    }
+
+    #[test]
+    fn test_animation() {
+        let source = "
+frames = [1, 3]
+
+[output]
+filename = \"frame{frame:02}.png\"
+width = 10
+height = 10
+
+[camera]
+lookfrom = [0.0, 0.0, 0.0]
+lookat = [0.0, 0.0, -1.0]
+vfov = 90.0
+
+[[camera.keys]]
+frame = 3
+lookfrom = [2.0, 0.0, 0.0]
+vfov = 30.0
+
+[[camera.keys]]
+frame = 1
+
+[materials.white]
+type = \"lambertian\"
+albedo = [0.5, 0.5, 0.5]
+
+[[objects]]
+type = \"bvh\"
+[[objects.objects]]
+type = \"moving_sphere\"
+center0 = [0.0, 0.0, -5.0]
+center1 = [1.0, 0.0, -5.0]
+time0 = 0.0
+time1 = 1.0
+radius = 0.5
+material = \"white\"
+[[objects.objects]]
+type = \"sphere\"
+center = [0.0, 0.0, -5.0]
+radius = 0.5
+material = \"white\"
+";
+        let mut scene = parse_scene(source, Path::new(".")).unwrap();
+        assert_eq!(scene.render_settings.frames, (1, 3));
+        // The BVH covers the sphere through the shutter of the last frame.
+        let bbox = scene.world.bounding_box(0., 1.).unwrap();
+        assert!((bbox.max().x - 3.5).abs() < 1e-9);
+
+        let key = scene.camera_animation.as_ref().unwrap().key(2);
+        assert_eq!(key.lookfrom, Vec3::new(1.0, 0.0, 0.0));
+        assert_eq!(key.lookat, Vec3::new(0.0, 0.0, -1.0));
+        assert_eq!(key.vfov, 60.0);
+        assert_eq!(scene.camera_animation.as_ref().unwrap().shutter(2), (1.0, 2.0));
+        scene.set_frame(2);
+        assert_eq!(scene.output_settings.template_values.frame, 2);
+
+        let e = parse_scene(&source.replace("frame = 1\n", "frame = 3\n"), Path::new(".")).unwrap_err();
+        assert_eq!(e.to_string(), "line 9: camera: more than one key for frame 3");
+    }
+}
@@ -0,0 +1,223 @@
+/*
+Daylight sky.
+
+The sky is the analytic model from "A Practical Analytic Model for Daylight"
+(Preetham, Shirley and Smits, 1999), which gives the luminance and
+chromaticity of a clear sky from the sun position and the turbidity (haze)
+of the air.  Their luminance is in kcd/m^2, which is scaled down by 50 so
+that (along with the default sun) a white surface in the midday sun comes
+out at about 1.
+
+The sun is a disk of constant radiance.  Its color comes from a simple
+estimate of how much light the atmosphere scatters away at the sun's
+elevation, so it turns orange near the horizon.
+*/
+
+use vec3::*;
+use util::*;
+use background::*;
+use pdf::random_on_unit_sphere;
+use onb::*;
+
+/// Scale from kcd/m^2 to the renderer's units.
+const LUMINANCE_SCALE: f64 = 0.02;
+/// Fraction of background samples aimed at the sun.
+const SUN_SAMPLE_FRACTION: f64 = 0.5;
+
+/// Coefficients of the Perez sky function for one channel.
+#[derive(Debug, Clone, Copy)]
+struct Perez {
+    a: f64,
+    b: f64,
+    c: f64,
+    d: f64,
+    e: f64,
+}
+
+impl Perez {
+    /// `cos_theta` is the cosine of the angle from the zenith, and `gamma`
+    /// the angle from the sun.
+    fn f(&self, cos_theta: f64, gamma: f64) -> f64 {
+        let cos_gamma = gamma.cos();
+        (1. + self.a * (self.b / cos_theta).exp()) *
+        (1. + self.c * (self.d * gamma).exp() + self.e * cos_gamma * cos_gamma)
+    }
+}
+
+#[derive(Debug)]
+pub struct Sky {
+    /// Unit vector towards the sun.
+    sun_direction: Vec3<f64>,
+    /// Perez coefficients for luminance and the x/y chromaticity.
+    perez: [Perez; 3],
+    /// Zenith luminance and chromaticity, divided by the Perez function at
+    /// the zenith (so the model only has to be multiplied out).
+    zenith: [f64; 3],
+    /// Cosine of the angular radius of the sun.
+    sun_cos_radius: f64,
+    /// Radiance of the sun disk.
+    sun_radiance: Vec3<f64>,
+    sun_onb: Onb,
+}
+
+impl Sky {
+    /// `turbidity` is the haziness of the air, from about 2 (very clear) to
+    /// 10 (hazy).  `sun_size` is the angular diameter of the sun in degrees
+    /// (0.53 for the real sun), and `sun_intensity` the irradiance from the
+    /// sun when it is overhead in clear air.  `intensity` scales everything.
+    pub fn new(sun_direction: Vec3<f64>,
+               turbidity: f64,
+               sun_size: f64,
+               sun_intensity: f64,
+               intensity: f64)
+               -> Sky {
+        let sun_direction = sun_direction.unit_vector();
+        let t = turbidity;
+        // The model doesn't cover the sun below the horizon.
+        let theta_sun = sun_direction.y.max(0.).min(1.).acos();
+        let perez = [
+            Perez { a: 0.1787 * t - 1.4630, b: -0.3554 * t + 0.4275, c: -0.0227 * t + 5.3251,
+                    d: 0.1206 * t - 2.5771, e: -0.0670 * t + 0.3703 },
+            Perez { a: -0.0193 * t - 0.2592, b: -0.0665 * t + 0.0008, c: -0.0004 * t + 0.2125,
+                    d: -0.0641 * t - 0.8989, e: -0.0033 * t + 0.0452 },
+            Perez { a: -0.0167 * t - 0.2608, b: -0.0950 * t + 0.0092, c: -0.0079 * t + 0.2102,
+                    d: -0.0441 * t - 1.6537, e: -0.0109 * t + 0.0529 },
+        ];
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_sun);
+        let zenith_y = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192) * LUMINANCE_SCALE;
+        let th = theta_sun;
+        let (th2, th3) = (th * th, th * th * th);
+        let zenith_x =
+            t * t * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th) +
+            t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394) +
+            (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_y_chroma =
+            t * t * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th) +
+            t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516) +
+            (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+        let zenith = [
+            zenith_y * intensity / perez[0].f(1., theta_sun),
+            zenith_x / perez[1].f(1., theta_sun),
+            zenith_y_chroma / perez[2].f(1., theta_sun),
+        ];
+
+        let radius = (sun_size / 2.).to_radians();
+        let sun_cos_radius = radius.cos();
+        let solid_angle = 2. * PI * (1. - sun_cos_radius);
+        let sun_radiance = sun_transmittance(theta_sun, t) * sun_intensity * intensity / solid_angle;
+        Sky {
+            sun_direction: sun_direction,
+            perez: perez,
+            zenith: zenith,
+            sun_cos_radius: sun_cos_radius,
+            sun_radiance: sun_radiance,
+            sun_onb: Onb::new_from_w(&sun_direction),
+        }
+    }
+
+    /// Radiance of the sky alone (without the sun disk).
+    fn sky_value(&self, direction: &Vec3<f64>) -> Vec3<f64> {
+        // Below the horizon, continue the color at the horizon.
+        let cos_theta = direction.y.max(0.001);
+        let gamma = dot(direction, &self.sun_direction).max(-1.).min(1.).acos();
+        let big_y = self.zenith[0] * self.perez[0].f(cos_theta, gamma);
+        let x = self.zenith[1] * self.perez[1].f(cos_theta, gamma);
+        let y = self.zenith[2] * self.perez[2].f(cos_theta, gamma);
+        xyy_to_rgb(x, y, big_y)
+    }
+}
+
+/// Rough color of sunlight after passing through the atmosphere, from the
+/// air mass (Kasten and Young) and per-channel extinction that grows with
+/// turbidity.
+fn sun_transmittance(theta_sun: f64, turbidity: f64) -> Vec3<f64> {
+    let degrees = theta_sun.to_degrees().min(90.);
+    let air_mass = 1. / (theta_sun.cos() + 0.50572 * (96.07995 - degrees).powf(-1.6364));
+    let extinction = Vec3::new(0.03, 0.06, 0.12) * (turbidity / 2.);
+    Vec3::new((-extinction.x * air_mass).exp(),
+              (-extinction.y * air_mass).exp(),
+              (-extinction.z * air_mass).exp())
+}
+
+/// CIE xyY to linear sRGB.
+fn xyy_to_rgb(x: f64, y: f64, big_y: f64) -> Vec3<f64> {
+    if y <= 0. {
+        return Vec3::zero();
+    }
+    let big_x = x / y * big_y;
+    let big_z = (1. - x - y) / y * big_y;
+    let r = 3.2406 * big_x - 1.5372 * big_y - 0.4986 * big_z;
+    let g = -0.9689 * big_x + 1.8758 * big_y + 0.0415 * big_z;
+    let b = 0.0557 * big_x - 0.2040 * big_y + 1.0570 * big_z;
+    Vec3::new(r.max(0.), g.max(0.), b.max(0.))
+}
+
+impl Background for Sky {
+    fn value(&self, direction: &Vec3<f64>) -> Vec3<f64> {
+        let d = direction.unit_vector();
+        let mut color = self.sky_value(&d);
+        if dot(&d, &self.sun_direction) >= self.sun_cos_radius && self.sun_direction.y > 0. {
+            color += self.sun_radiance;
+        }
+        color
+    }
+
+    fn is_sampled(&self) -> bool {
+        true
+    }
+
+    fn pdf_value(&self, direction: &Vec3<f64>) -> f64 {
+        let d = direction.unit_vector();
+        let mut pdf = (1. - SUN_SAMPLE_FRACTION) / (4. * PI);
+        if dot(&d, &self.sun_direction) >= self.sun_cos_radius {
+            pdf += SUN_SAMPLE_FRACTION / (2. * PI * (1. - self.sun_cos_radius));
+        }
+        pdf
+    }
+
+    fn random(&self, rng: &mut Rng) -> Vec3<f64> {
+        if rng.rand64() < SUN_SAMPLE_FRACTION {
+            // Uniform over the cone of the sun.
+            let z = 1. - rng.rand64() * (1. - self.sun_cos_radius);
+            let r = (1. - z * z).max(0.).sqrt();
+            let phi = 2. * PI * rng.rand64();
+            self.sun_onb.local_scalar(r * phi.cos(), r * phi.sin(), z)
+        } else {
+            random_on_unit_sphere(rng)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sky() {
+        let noon = Sky::new(Vec3::new(0.2, 1., 0.), 3., 0.53, 6., 1.);
+        let zenith = noon.value(&Vec3::new(0., 1., 0.));
+        assert!(zenith.z > zenith.x && zenith.x > 0.05 && zenith.z < 2., "{:?}", zenith);
+        // Looking at the sun is much brighter than the sky around it.
+        let sun = noon.value(&noon.sun_direction);
+        assert!(sun.x > 1000. * zenith.x, "{:?}", sun);
+
+        // A low sun is orange.
+        let evening = Sky::new(Vec3::new(1., 0.05, 0.), 3., 0.53, 6., 1.);
+        let sun = evening.value(&evening.sun_direction);
+        assert!(sun.x > sun.y && sun.y > sun.z, "{:?}", sun);
+
+        // The pdf integrates to 1 (with a big sun so that uniform samples
+        // find it).
+        let sky = Sky::new(Vec3::new(1., 1., 0.), 3., 40., 6., 1.);
+        let mut rng = Rng::from_seed(0);
+        let n = 200000;
+        let mut total = 0.;
+        for _ in 0..n {
+            total += sky.pdf_value(&random_on_unit_sphere(&mut rng)) * 4. * PI;
+        }
+        assert!((total / n as f64 - 1.).abs() < 0.02, "{}", total / n as f64);
+        for _ in 0..100 {
+            assert!(sky.pdf_value(&sky.random(&mut rng)) > 0.);
+        }
+    }
+}
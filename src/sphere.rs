@@ -11,11 +11,11 @@ use pdf::*;
 pub struct Sphere {
     center: Vec3<f64>,
     radius: f64,
-    material: Rc<Material>,
+    material: Arc<Material>,
 }
 
 impl Sphere {
-    pub fn new(cen: Vec3<f64>, r: f64, material: Rc<Material>) -> Sphere {
+    pub fn new(cen: Vec3<f64>, r: f64, material: Arc<Material>) -> Sphere {
         Sphere {
             center: cen,
             radius: r,
@@ -63,7 +63,16 @@ impl Hitable for Sphere {
                        self.center + Vec3::new(self.radius, self.radius, self.radius)))
     }
 
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+
+    // From inside, every direction hits the sphere, so they are sampled
+    // uniformly.
     fn pdf_value(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        if (self.center - *o).squared_length() <= self.radius * self.radius {
+            return 1. / (4. * PI);
+        }
         if let Some(hrec) = self.hit(rng, &Ray::new(o.clone(), v.clone()), 0.001, f64::MAX) {
             let cos_theta_max = (1. - self.radius*self.radius/(self.center-*o).squared_length()).sqrt();
             let solid_angle = 2.*PI*(1.-cos_theta_max);
@@ -75,6 +84,9 @@ impl Hitable for Sphere {
     fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> {
         let direction = self.center - *o;
         let distance_squared = direction.squared_length();
+        if distance_squared <= self.radius * self.radius {
+            return random_on_unit_sphere(rng);
+        }
         let uvw = Onb::new_from_w(&direction);
         return uvw.local_vec(&random_to_sphere(rng, self.radius, distance_squared));
     }
@@ -88,3 +100,18 @@ fn get_sphere_uv(p: &Vec3<f64>) -> (f64, f64) {
     let v = (theta + PI/2.) / PI;
     return (u,v);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use texture::*;
+
+    #[test]
+    fn test_sampling() {
+        let m: Arc<Material> = Arc::new(DiffuseLight::new(Box::new(ConstantTexture::new(Vec3::new(1., 1., 1.)))));
+        let sphere = Sphere::new(Vec3::new(0.5, 1., -0.5), 0.8, m);
+        check_sampling(&sphere, &Vec3::new(0., -1., 1.));
+        check_sampling(&sphere, &Vec3::new(3., 1., 0.));
+        check_sampling(&sphere, &Vec3::new(0.5, 1.2, -0.5));
+    }
+}
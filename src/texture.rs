@@ -4,7 +4,7 @@ use perlin::*;
 use image;
 use image::{GenericImage, Pixel};
 
-pub trait Texture: fmt::Debug {
+pub trait Texture: fmt::Debug + Send + Sync {
     /// Return the color of the texture at the given UV coordinate.
     ///
     /// p is the hit location (in world coordinates).
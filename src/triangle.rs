@@ -0,0 +1,405 @@
+use vec3::*;
+use ray::Ray;
+use aabb::*;
+use hitable::*;
+use material::*;
+use bvh::*;
+use util::*;
+
+/// Padding added to triangle bounding boxes, so that triangles lying in an
+/// axis-aligned plane don't end up with a box that has no thickness.
+const BOX_PADDING: f64 = 0.0001;
+
+/// Intersect a ray with the triangle formed by the vertices v
+/// (Möller-Trumbore).
+///
+/// Returns the ray parameter and the barycentric coordinates of the second
+/// and third vertex.
+#[inline]
+fn intersect(v: &[Vec3<f64>; 3], r: &Ray<f64>, t_min: f64, t_max: f64) -> Option<(f64, f64, f64)> {
+    let e1 = v[1] - v[0];
+    let e2 = v[2] - v[0];
+    let pvec = cross(&r.direction(), &e2);
+    let det = dot(&e1, &pvec);
+    // Ray is parallel to the triangle.
+    if det == 0. {
+        return None;
+    }
+    let inv_det = 1. / det;
+    let tvec = r.origin() - v[0];
+    let b1 = dot(&tvec, &pvec) * inv_det;
+    if b1 < 0. || b1 > 1. {
+        return None;
+    }
+    let qvec = cross(&tvec, &e1);
+    let b2 = dot(&r.direction(), &qvec) * inv_det;
+    if b2 < 0. || b1 + b2 > 1. {
+        return None;
+    }
+    let t = dot(&e2, &qvec) * inv_det;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+/// Interpolate a value across a triangle with barycentric coordinates.
+#[inline]
+fn interpolate(a: &[Vec3<f64>; 3], b1: f64, b2: f64) -> Vec3<f64> {
+    (1. - b1 - b2) * a[0] + b1 * a[1] + b2 * a[2]
+}
+
+#[inline]
+fn interpolate_uv(a: &[(f64, f64); 3], b1: f64, b2: f64) -> (f64, f64) {
+    let b0 = 1. - b1 - b2;
+    (b0 * a[0].0 + b1 * a[1].0 + b2 * a[2].0,
+     b0 * a[0].1 + b1 * a[1].1 + b2 * a[2].1)
+}
+
+fn triangle_area(v: &[Vec3<f64>; 3]) -> f64 {
+    0.5 * cross(&(v[1] - v[0]), &(v[2] - v[0])).length()
+}
+
+fn triangle_bounding_box(v: &[Vec3<f64>; 3]) -> AABB {
+    let pad = Vec3::new(BOX_PADDING, BOX_PADDING, BOX_PADDING);
+    let bbox = surrounding_box(&AABB::new(v[0], v[0]), &AABB::new(v[1], v[1]));
+    let bbox = surrounding_box(&bbox, &AABB::new(v[2], v[2]));
+    AABB::new(bbox.min() - pad, bbox.max() + pad)
+}
+
+/// Pick a uniformly distributed point on a triangle.
+fn random_point(rng: &mut Rng, v: &[Vec3<f64>; 3]) -> Vec3<f64> {
+    let s = rng.rand64().sqrt();
+    let b1 = rng.rand64() * s;
+    let b2 = 1. - s;
+    interpolate(v, b1, b2)
+}
+
+/// Solid angle PDF of sampling a point uniformly by area, for a hit at
+/// distance t along direction v, with surface normal `normal`.
+fn area_pdf_value(t: f64, v: &Vec3<f64>, normal: &Vec3<f64>, area: f64) -> f64 {
+    let distance_squared = t * t * v.squared_length();
+    let cosine = dot(v, normal).abs() / (v.length() * normal.length());
+    distance_squared / (cosine * area)
+}
+
+/// A single triangle.
+///
+/// The front face is the side where the vertices are in counter-clockwise
+/// order.
+#[derive(Debug, Clone)]
+pub struct Triangle {
+    vertices: [Vec3<f64>; 3],
+    /// Normal at each vertex (interpolated across the face).  If None, the
+    /// face normal is used.
+    normals: Option<[Vec3<f64>; 3]>,
+    /// UV coordinates at each vertex.
+    uvs: [(f64, f64); 3],
+    material: Arc<Material>,
+}
+
+impl Triangle {
+    pub fn new(vertices: [Vec3<f64>; 3], material: Arc<Material>) -> Triangle {
+        Triangle::new_with_attributes(vertices, None, None, material)
+    }
+
+    /// Create a triangle with per-vertex normals and/or UVs.
+    ///
+    /// Without UVs, the vertices get (0, 0), (1, 0) and (0, 1).
+    pub fn new_with_attributes(vertices: [Vec3<f64>; 3],
+                               normals: Option<[Vec3<f64>; 3]>,
+                               uvs: Option<[(f64, f64); 3]>,
+                               material: Arc<Material>)
+                               -> Triangle {
+        Triangle {
+            vertices: vertices,
+            normals: normals,
+            uvs: uvs.unwrap_or([(0., 0.), (1., 0.), (0., 1.)]),
+            material: material,
+        }
+    }
+
+    fn face_normal(&self) -> Vec3<f64> {
+        cross(&(self.vertices[1] - self.vertices[0]),
+              &(self.vertices[2] - self.vertices[0])).unit_vector()
+    }
+}
+
+impl Hitable for Triangle {
+    fn hit(&self, _: &mut Rng, r: &Ray<f64>, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        intersect(&self.vertices, r, t_min, t_max).map(|(t, b1, b2)| {
+            let normal = match self.normals {
+                Some(ref normals) => interpolate(normals, b1, b2).unit_vector(),
+                None => self.face_normal(),
+            };
+            let (u, v) = interpolate_uv(&self.uvs, b1, b2);
+            HitRecord::new(t, u, v, r.point_at_parameter(t), normal, self.material.clone())
+        })
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(triangle_bounding_box(&self.vertices))
+    }
+
+    fn pdf_value(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        let r = Ray::new(o.clone(), v.clone());
+        if let Some((t, _, _)) = intersect(&self.vertices, &r, 0.001, f64::MAX) {
+            return area_pdf_value(t, v, &self.face_normal(), triangle_area(&self.vertices));
+        } else {
+            return 0.;
+        }
+    }
+
+    fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> {
+        random_point(rng, &self.vertices) - *o
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.material.is_emissive()
+    }
+}
+
+/// Vertex data shared by all the triangles in a mesh.
+#[derive(Debug)]
+struct MeshData {
+    positions: Vec<Vec3<f64>>,
+    /// Per-vertex normals, either empty or the same length as positions.
+    normals: Vec<Vec3<f64>>,
+    /// Per-vertex UVs, either empty or the same length as positions.
+    uvs: Vec<(f64, f64)>,
+    /// Indices of the three vertices of each triangle.
+    triangles: Vec<[usize; 3]>,
+    /// Running total of the triangle areas, for picking a triangle with
+    /// probability proportional to its area.
+    area_cdf: Vec<f64>,
+    material: Arc<Material>,
+}
+
+impl MeshData {
+    fn vertices(&self, index: usize) -> [Vec3<f64>; 3] {
+        let tri = &self.triangles[index];
+        [self.positions[tri[0]], self.positions[tri[1]], self.positions[tri[2]]]
+    }
+
+    fn area(&self) -> f64 {
+        *self.area_cdf.last().unwrap()
+    }
+}
+
+/// One triangle of a mesh, used as the primitive for the mesh's BVH.
+#[derive(Debug)]
+struct MeshTriangle {
+    mesh: Arc<MeshData>,
+    index: usize,
+}
+
+impl Hitable for MeshTriangle {
+    fn hit(&self, _: &mut Rng, r: &Ray<f64>, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        let vertices = self.mesh.vertices(self.index);
+        intersect(&vertices, r, t_min, t_max).map(|(t, b1, b2)| {
+            let tri = &self.mesh.triangles[self.index];
+            let normal = if self.mesh.normals.is_empty() {
+                cross(&(vertices[1] - vertices[0]), &(vertices[2] - vertices[0])).unit_vector()
+            } else {
+                let n = &self.mesh.normals;
+                interpolate(&[n[tri[0]], n[tri[1]], n[tri[2]]], b1, b2).unit_vector()
+            };
+            let (u, v) = if self.mesh.uvs.is_empty() {
+                (b1, b2)
+            } else {
+                let uv = &self.mesh.uvs;
+                interpolate_uv(&[uv[tri[0]], uv[tri[1]], uv[tri[2]]], b1, b2)
+            };
+            HitRecord::new(t, u, v, r.point_at_parameter(t), normal, self.mesh.material.clone())
+        })
+    }
+
+    fn bounding_box(&self, _t0: f64, _t1: f64) -> Option<AABB> {
+        Some(triangle_bounding_box(&self.mesh.vertices(self.index)))
+    }
+
+    /// This triangle's part of the pdf of sampling the whole mesh by area.
+    fn pdf_value(&self, _: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        let vertices = self.mesh.vertices(self.index);
+        match intersect(&vertices, &Ray::new(o.clone(), v.clone()), 0.001, f64::MAX) {
+            Some((t, _, _)) => {
+                let normal = cross(&(vertices[1] - vertices[0]), &(vertices[2] - vertices[0]));
+                area_pdf_value(t, v, &normal, self.mesh.area())
+            },
+            None => 0.,
+        }
+    }
+}
+
+/// An indexed triangle mesh.
+///
+/// The triangles are stored in their own BVH.  Sampling the mesh (when it is
+/// used as a light) picks a point uniformly over its whole surface.
+#[derive(Debug)]
+pub struct TriangleMesh {
+    mesh: Arc<MeshData>,
+    bvh: BVH,
+}
+
+impl TriangleMesh {
+    /// Create a new mesh.
+    ///
+    /// * `positions`: Vertex positions.
+    /// * `normals`: Vertex normals.  Either empty (use the face normals) or
+    ///   one per position.
+    /// * `uvs`: Vertex UV coordinates.  Either empty (use the barycentric
+    ///   coordinates) or one per position.
+    /// * `triangles`: Indices into the vertex lists for each triangle.
+    ///   Counter-clockwise is the front face.
+    ///
+    /// Panics if there are no triangles, or the indices or attribute lists
+    /// don't match the positions.
+    pub fn new(positions: Vec<Vec3<f64>>,
+               normals: Vec<Vec3<f64>>,
+               uvs: Vec<(f64, f64)>,
+               triangles: Vec<[usize; 3]>,
+               material: Arc<Material>)
+               -> TriangleMesh {
+        assert!(!triangles.is_empty(), "mesh has no triangles");
+        assert!(normals.is_empty() || normals.len() == positions.len());
+        assert!(uvs.is_empty() || uvs.len() == positions.len());
+        assert!(triangles.iter().all(|tri| tri.iter().all(|&i| i < positions.len())),
+                "mesh triangle index out of range");
+        let mut area_cdf = Vec::with_capacity(triangles.len());
+        let mut total = 0.;
+        for tri in &triangles {
+            total += triangle_area(&[positions[tri[0]], positions[tri[1]], positions[tri[2]]]);
+            area_cdf.push(total);
+        }
+        let mesh = Arc::new(MeshData {
+            positions: positions,
+            normals: normals,
+            uvs: uvs,
+            triangles: triangles,
+            area_cdf: area_cdf,
+            material: material,
+        });
+        let prims: Vec<Box<Hitable>> = (0..mesh.triangles.len()).map(|i| {
+            Box::new(MeshTriangle { mesh: mesh.clone(), index: i }) as Box<Hitable>
+        }).collect();
+        let bvh = BVH::new(prims, 0., 1.);
+        TriangleMesh {
+            mesh: mesh,
+            bvh: bvh,
+        }
+    }
+
+    pub fn num_triangles(&self) -> usize {
+        self.mesh.triangles.len()
+    }
+
+    /// Total surface area of the mesh.
+    pub fn area(&self) -> f64 {
+        self.mesh.area()
+    }
+}
+
+impl Hitable for TriangleMesh {
+    fn hit(&self, rng: &mut Rng, r: &Ray<f64>, t_min: f64, t_max: f64) -> Option<HitRecord> {
+        self.bvh.hit(rng, r, t_min, t_max)
+    }
+
+    fn bounding_box(&self, t0: f64, t1: f64) -> Option<AABB> {
+        self.bvh.bounding_box(t0, t1)
+    }
+
+    fn pdf_value(&self, rng: &mut Rng, o: &Vec3<f64>, v: &Vec3<f64>) -> f64 {
+        // Points are sampled over the whole surface, including the sides
+        // facing away from `o`, so every triangle along the ray counts.
+        self.bvh.pdf_value_sum(rng, o, v)
+    }
+
+    fn random(&self, rng: &mut Rng, o: &Vec3<f64>) -> Vec3<f64> {
+        let area_cdf = &self.mesh.area_cdf;
+        let x = rng.rand64() * self.area();
+        let index = match area_cdf.binary_search_by(|a| a.partial_cmp(&x).unwrap()) {
+            Ok(i) => i,
+            Err(i) => i,
+        };
+        let index = index.min(area_cdf.len() - 1);
+        random_point(rng, &self.mesh.vertices(index)) - *o
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.mesh.material.is_emissive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use texture::*;
+
+    fn material() -> Arc<Material> {
+        Arc::new(Lambertian::new(Box::new(ConstantTexture::new(Vec3::new(0.5, 0.5, 0.5)))))
+    }
+
+    #[test]
+    fn test_triangle_hit() {
+        let mut rng = Rng::from_seed(0);
+        let tri = Triangle::new_with_attributes(
+            [Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.), Vec3::new(0., 1., 0.)],
+            None,
+            Some([(0., 0.), (1., 0.), (0., 1.)]),
+            material());
+        let r = Ray::new(Vec3::new(0.25, 0.5, 1.), Vec3::new(0., 0., -1.));
+        let rec = tri.hit(&mut rng, &r, 0., f64::MAX).unwrap();
+        assert_eq!(rec.t, 1.);
+        assert_eq!(rec.normal, Vec3::new(0., 0., 1.));
+        assert_eq!((rec.u, rec.v), (0.25, 0.5));
+        // Outside the triangle.
+        let r = Ray::new(Vec3::new(0.75, 0.5, 1.), Vec3::new(0., 0., -1.));
+        assert!(tri.hit(&mut rng, &r, 0., f64::MAX).is_none());
+    }
+
+    #[test]
+    fn test_mesh_hit() {
+        let mut rng = Rng::from_seed(0);
+        // A unit square made of two triangles, with normals that lean
+        // towards +x.
+        let n = Vec3::new(1., 0., 1.).unit_vector();
+        let mesh = TriangleMesh::new(
+            vec![Vec3::new(0., 0., 0.), Vec3::new(1., 0., 0.), Vec3::new(1., 1., 0.), Vec3::new(0., 1., 0.)],
+            vec![n, n, n, n],
+            vec![],
+            vec![[0, 1, 2], [0, 2, 3]],
+            material());
+        assert_eq!(mesh.area(), 1.);
+        let r = Ray::new(Vec3::new(0.25, 0.75, 2.), Vec3::new(0., 0., -1.));
+        let rec = mesh.hit(&mut rng, &r, 0., f64::MAX).unwrap();
+        assert_eq!(rec.t, 2.);
+        assert!((rec.normal - n).length() < 1e-12);
+        let r = Ray::new(Vec3::new(1.5, 0.5, 2.), Vec3::new(0., 0., -1.));
+        assert!(mesh.hit(&mut rng, &r, 0., f64::MAX).is_none());
+    }
+
+    #[test]
+    fn test_sampling() {
+        let tri = Triangle::new([Vec3::new(-1., 0., -1.), Vec3::new(2., 0.5, -1.), Vec3::new(0., 1.5, 1.)],
+                                material());
+        check_sampling(&tri, &Vec3::new(0.2, 2., 0.3));
+
+        // A closed box, so rays towards it pass through two sampled faces.
+        // The vertex normals point out from the center, away from the face
+        // normals.
+        let positions: Vec<Vec3<f64>> = (0..8).map(|i| {
+            Vec3::new((i & 1) as f64, ((i >> 1) & 1) as f64, ((i >> 2) & 1) as f64)
+        }).collect();
+        let normals = positions.iter().map(|p| (*p - Vec3::new(0.5, 0.5, 0.5)).unit_vector()).collect();
+        let mesh = TriangleMesh::new(
+            positions,
+            normals,
+            vec![],
+            vec![[0, 2, 1], [1, 2, 3], [4, 5, 6], [5, 7, 6],
+                 [0, 1, 4], [1, 5, 4], [2, 6, 3], [3, 6, 7],
+                 [0, 4, 2], [2, 4, 6], [1, 3, 5], [3, 7, 5]],
+            material());
+        assert_eq!(mesh.area(), 6.);
+        check_sampling(&mesh, &Vec3::new(1.5, 2., -0.8));
+    }
+}
@@ -1,6 +1,8 @@
 use rand;
+use vec3::*;
 use rand::Rng as R;
-pub use std::rc::Rc;
+use rand::SeedableRng;
+pub use std::sync::Arc;
 pub use std::fmt;
 pub use std::f64::consts::PI;
 pub use std::f64;
@@ -11,15 +13,65 @@ pub struct Rng {
 }
 
 impl Rng {
+    /// Create a generator with a random seed.
     pub fn new() -> Rng {
         Rng { rng: rand::weak_rng() }
     }
+
+    /// Create a generator that always produces the same sequence for the
+    /// same seed.
+    pub fn from_seed(seed: u64) -> Rng {
+        let mut state = seed;
+        let a = splitmix64(&mut state);
+        let b = splitmix64(&mut state);
+        let mut words = [a as u32, (a >> 32) as u32, b as u32, (b >> 32) as u32];
+        // XorShift can't be seeded with all zeros.
+        if words == [0, 0, 0, 0] {
+            words[0] = 1;
+        }
+        Rng { rng: rand::XorShiftRng::from_seed(words) }
+    }
+
+    /// Create the generator for a single sample of a pixel.
+    ///
+    /// Each sample gets its own stream so that the result does not depend on
+    /// the order pixels are rendered in (or how many threads are used).
+    pub fn for_sample(seed: u64, x: u32, y: u32, sample: u32) -> Rng {
+        // Hash each coordinate in turn.  Combining them without hashing in
+        // between lets different pixels and samples end up with the same
+        // stream.
+        let h = hash_combine(seed, (x as u64) << 32 | y as u64);
+        Rng::from_seed(hash_combine(h, sample as u64))
+    }
+
     #[inline(always)]
     pub fn rand64(&mut self) -> f64 {
         self.rng.gen::<f64>()
     }
 }
 
+/// SplitMix64, used to turn seeds into well-distributed generator state.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9E3779B97F4A7C15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+    z ^ (z >> 31)
+}
+
+/// Mix the value `v` into the hash `h`.
+fn hash_combine(h: u64, v: u64) -> u64 {
+    let mut state = h;
+    let mut state = splitmix64(&mut state) ^ v;
+    splitmix64(&mut state)
+}
+
+/// Relative luminance of a linear RGB color.
+#[inline(always)]
+pub fn luminance(c: &Vec3<f64>) -> f64 {
+    0.2126 * c.x + 0.7152 * c.y + 0.0722 * c.z
+}
+
 #[macro_export]
 macro_rules! perrln {
     ($($arg:tt)*) => ({
@@ -27,3 +79,39 @@ macro_rules! perrln {
         writeln!(&mut stderr(), $($arg)*).ok();
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_seeded_rng() {
+        let mut a = Rng::from_seed(42);
+        let mut b = Rng::from_seed(42);
+        for _ in 0..100 {
+            assert_eq!(a.rand64(), b.rand64());
+        }
+        // A zero seed must still work.
+        let mut z = Rng::from_seed(0);
+        assert!(z.rand64() != z.rand64());
+    }
+
+    #[test]
+    fn test_sample_rng() {
+        let first = Rng::for_sample(1, 10, 20, 0).rand64();
+        assert_eq!(first, Rng::for_sample(1, 10, 20, 0).rand64());
+        assert!(first != Rng::for_sample(1, 10, 20, 1).rand64());
+        assert!(first != Rng::for_sample(1, 20, 10, 0).rand64());
+        assert!(first != Rng::for_sample(2, 10, 20, 0).rand64());
+        // Nearby pixels and samples must not share streams.
+        let mut firsts: Vec<f64> = Vec::new();
+        for y in 0..16 {
+            for sample in 0..16 {
+                firsts.push(Rng::for_sample(1, 10, y, sample).rand64());
+            }
+        }
+        firsts.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        firsts.dedup();
+        assert_eq!(firsts.len(), 16 * 16);
+    }
+}